@@ -0,0 +1,193 @@
+//! Typed access to the values of fields. The getters on `Record`
+//! read values using the recutils formats for each type (so booleans
+//! may be `yes`/`no`, `true`/`false` or `1`/`0`), and a `TypedRecord`
+//! additionally checks values against the `%type`s declared by a
+//! record descriptor.
+
+use std::str::FromStr;
+
+use types::{parse_bool, parse_int, parse_real};
+use {date, FieldType, RecError, Recfile, Record, RecordDescriptor};
+
+/// A `Record` paired with the descriptor for its type, if it has one
+#[derive(Debug, Clone, Copy)]
+pub struct TypedRecord<'a> {
+    pub record: &'a Record,
+    pub descriptor: Option<&'a RecordDescriptor>,
+}
+
+fn invalid(name: &str, value: &str, expected: &str) -> RecError {
+    RecError::InvalidValue {
+        name: name.to_owned(),
+        value: value.to_owned(),
+        expected: expected.to_owned(),
+    }
+}
+
+impl<'a> TypedRecord<'a> {
+    /// Return the first value of a field, checking it against the
+    /// field's declared type if there is one
+    pub fn get(&self, name: &str) -> Result<&'a str, RecError> {
+        let value = self.record.get(name)?;
+        if let Some(desc) = self.descriptor {
+            desc.check_field(name, value)?;
+        }
+        Ok(value)
+    }
+
+    fn get_with<T, F>(&self, name: &str, expected: &str, f: F) -> Result<T, RecError>
+        where F: Fn(&str) -> Option<T>
+    {
+        let value = self.get(name)?;
+        f(value).ok_or_else(|| invalid(name, value, expected))
+    }
+
+    /// Read a field as an integer, in decimal or `0x` hexadecimal
+    pub fn get_int(&self, name: &str) -> Result<i64, RecError> {
+        self.get_with(name, "int", parse_int)
+    }
+
+    /// Read a field as a real number
+    pub fn get_real(&self, name: &str) -> Result<f64, RecError> {
+        self.get_with(name, "real", parse_real)
+    }
+
+    /// Read a field as a boolean
+    pub fn get_bool(&self, name: &str) -> Result<bool, RecError> {
+        self.get_with(name, "bool", parse_bool)
+    }
+
+    /// Read a field as a date, returning the number of seconds since
+    /// the Unix epoch
+    pub fn get_date(&self, name: &str) -> Result<i64, RecError> {
+        self.get_with(name, "date", date::parse)
+    }
+
+    /// Read a field which must be one of a fixed set of values. If
+    /// no values are given, the ones declared by an `enum` type are
+    /// used instead.
+    pub fn get_enum(&self, name: &str, values: &[&str]) -> Result<&'a str, RecError> {
+        let declared = match self.descriptor.map(|d| d.field_type(name)) {
+            Some(Ok(Some(FieldType::Enum(vals)))) => vals,
+            _ => vec![],
+        };
+        let value = self.get(name)?;
+        let trimmed = value.trim();
+        let allowed = if values.is_empty() {
+            declared.iter().any(|v| v == trimmed)
+        } else {
+            values.contains(&trimmed)
+        };
+        if allowed {
+            Ok(trimmed)
+        } else {
+            let expected = if values.is_empty() { declared.join(" ") } else { values.join(" ") };
+            Err(invalid(name, value, &format!("enum {}", expected)))
+        }
+    }
+
+    /// Read a field using its `FromStr` implementation
+    pub fn get_parsed<T: FromStr>(&self, name: &str) -> Result<T, RecError> {
+        self.get_with(name, std::any::type_name::<T>(), |v| v.parse().ok())
+    }
+}
+
+impl Record {
+    /// View this record along with the descriptor for its type, so
+    /// that its values are checked against their declared types
+    pub fn typed<'a>(&'a self, descriptor: Option<&'a RecordDescriptor>) -> TypedRecord<'a> {
+        TypedRecord { record: self, descriptor }
+    }
+
+    /// Read a field as an integer, in decimal or `0x` hexadecimal
+    pub fn get_int(&self, name: &str) -> Result<i64, RecError> {
+        self.typed(None).get_int(name)
+    }
+
+    /// Read a field as a real number
+    pub fn get_real(&self, name: &str) -> Result<f64, RecError> {
+        self.typed(None).get_real(name)
+    }
+
+    /// Read a field as a boolean, which may be any of `yes`/`no`,
+    /// `true`/`false` or `1`/`0`
+    pub fn get_bool(&self, name: &str) -> Result<bool, RecError> {
+        self.typed(None).get_bool(name)
+    }
+
+    /// Read a field as a date, returning the number of seconds since
+    /// the Unix epoch
+    pub fn get_date(&self, name: &str) -> Result<i64, RecError> {
+        self.typed(None).get_date(name)
+    }
+
+    /// Read a field which must be one of a fixed set of values
+    pub fn get_enum<'a>(&'a self, name: &str, values: &[&str]) -> Result<&'a str, RecError> {
+        self.typed(None).get_enum(name, values)
+    }
+
+    /// Read a field using its `FromStr` implementation
+    pub fn get_parsed<T: FromStr>(&self, name: &str) -> Result<T, RecError> {
+        self.typed(None).get_parsed(name)
+    }
+}
+
+impl Recfile {
+    /// View a record along with the descriptor for its type in this
+    /// file
+    pub fn typed<'a>(&'a self, rec: &'a Record) -> TypedRecord<'a> {
+        rec.typed(self.descriptor_for(rec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {RecError, Recfile};
+
+    const SRC: &[u8] = b"%rec: Item
+%type: Count range 0 10
+%type: Kind enum small large
+%type: Flag bool
+
+Count: 0x0c
+Price: 1.5
+Flag: yes
+Added: 2011-04-20
+Kind: small
+Note: hello
+";
+
+    #[test]
+    fn untyped_getters() {
+        let file = Recfile::parse(SRC).unwrap();
+        let rec = &file.records[0];
+        assert_eq!(rec.get_int("Count"), Ok(12));
+        assert_eq!(rec.get_real("Price"), Ok(1.5));
+        assert_eq!(rec.get_bool("Flag"), Ok(true));
+        assert_eq!(rec.get_date("Added"), Ok(1303257600));
+        assert_eq!(rec.get_enum("Kind", &["small", "large"]), Ok("small"));
+        assert_eq!(rec.get_parsed::<f32>("Price"), Ok(1.5));
+        assert_eq!(rec.get_int("Note"), Err(RecError::InvalidValue {
+            name: "Note".to_owned(),
+            value: "hello".to_owned(),
+            expected: "int".to_owned(),
+        }));
+        assert_eq!(rec.get_int("Missing"), Err(RecError::MissingField {
+            name: "Missing".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn declared_types() {
+        let file = Recfile::parse(SRC).unwrap();
+        let rec = file.typed(&file.records[0]);
+        assert_eq!(rec.get_bool("Flag"), Ok(true));
+        assert_eq!(rec.get_enum("Kind", &[]), Ok("small"));
+        assert_eq!(rec.get_int("Count"), Err(RecError::TypeMismatch {
+            name: "Count".to_owned(),
+            value: "0x0c".to_owned(),
+            typ: "range 0 10".to_owned(),
+        }));
+        assert!(file.records[0].get_enum("Kind", &[]).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Parsing without copying. A `RecfileRef` is parsed from a string
+//! which outlives it (such as the contents of a memory-mapped file),
+//! and its records refer to the field names and values in that
+//! string instead of allocating their own. Only values which span
+//! several lines, and so have to be joined, are allocated.
+
+use std::borrow::Cow;
+
+use reader::{ParseOptions, StrReader};
+use {Position, RecError, Recfile, Record, RecordDescriptor, Span};
+
+/// A record whose fields borrow from the source it was parsed from.
+/// As with `Record`, two records are equal if their types and fields
+/// are equal.
+#[derive(Eq, Debug, Clone)]
+pub struct BorrowedRecord<'a> {
+    pub rec_type: Option<Cow<'a, str>>,
+    pub fields: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    /// The location of this record in its source
+    pub span: Option<Span>,
+    /// The locations of each of the fields, as in `Record::field_spans`
+    pub field_spans: Vec<Span>,
+    /// The places where values were split across lines with a
+    /// trailing backslash, as in `Record::splits`
+    pub splits: Vec<(usize, usize)>,
+}
+
+impl<'a> PartialEq for BorrowedRecord<'a> {
+    fn eq(&self, other: &BorrowedRecord<'a>) -> bool {
+        self.rec_type == other.rec_type && self.fields == other.fields
+    }
+}
+
+impl<'a> BorrowedRecord<'a> {
+    /// Return the first value of a field
+    pub fn get(&self, name: &str) -> Result<&str, RecError> {
+        self.fields.iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_ref())
+            .ok_or_else(|| RecError::MissingField { name: name.to_owned() })
+    }
+
+    /// Return every value of a field, in order
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.fields.iter()
+            .filter(|(k, _)| k == name)
+            .map(|(_, v)| v.as_ref())
+            .collect()
+    }
+
+    /// Copy this record into an owned `Record`
+    pub fn to_record(&self) -> Record {
+        self.clone().into_record()
+    }
+
+    /// Turn this record into an owned `Record`, only copying the
+    /// names and values which are borrowed
+    pub fn into_record(self) -> Record {
+        Record {
+            span: self.span,
+            field_spans: self.field_spans,
+            splits: self.splits,
+            ..Record::new(self.rec_type.as_deref(),
+                          self.fields.into_iter().map(|(k, v)| (k.into_owned(), v.into_owned())))
+        }
+    }
+}
+
+/// A recfile whose records borrow from the source it was parsed from
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct RecfileRef<'a> {
+    pub records: Vec<BorrowedRecord<'a>>,
+    pub descriptors: Vec<RecordDescriptor>,
+}
+
+/// The first word of a `%rec` value, which is the type it describes
+fn first_word<'a>(value: &Cow<'a, str>) -> Cow<'a, str> {
+    match value {
+        Cow::Borrowed(s) => Cow::Borrowed(s.split_whitespace().next().unwrap_or("")),
+        Cow::Owned(s) => Cow::Owned(s.split_whitespace().next().unwrap_or("").to_owned()),
+    }
+}
+
+impl<'a> RecfileRef<'a> {
+    /// Parse a whole recfile, borrowing its names and values from
+    /// the source
+    pub fn parse(source: &'a str) -> Result<RecfileRef<'a>, RecError> {
+        RecfileRef::parse_with(source, &ParseOptions::default()).map(|(file, _)| file)
+    }
+
+    /// Parse a whole recfile according to some `ParseOptions`,
+    /// returning it along with any warnings about the input
+    pub fn parse_with(source: &'a str, options: &ParseOptions)
+                      -> Result<(RecfileRef<'a>, Vec<RecError>), RecError> {
+        let mut file = RecfileRef { records: vec![], descriptors: vec![] };
+        let mut reader = StrReader::new(source, options);
+        let mut rec_type = None;
+
+        while let Some(rec) = reader.next_raw() {
+            let mut rec = rec?;
+            let desc = match rec.fields.iter().find(|(k, _)| k == "%rec") {
+                Some((_, v)) => first_word(v),
+                None => {
+                    if options.max_records.is_some_and(|max| file.records.len() >= max) {
+                        let span = rec.span.unwrap_or_default();
+                        return Err(RecError::LimitExceeded {
+                            what: "records".to_owned(),
+                            limit: options.max_records.unwrap_or_default(),
+                            pos: Position { file: None, line: span.start, column: 1, offset: span.start_offset },
+                        });
+                    }
+                    rec.rec_type = rec_type.clone();
+                    file.records.push(rec);
+                    continue;
+                }
+            };
+            file.descriptors.push(RecordDescriptor::from_record(&rec.to_record())?);
+            rec_type = Some(desc);
+        }
+        Ok((file, reader.warnings().to_vec()))
+    }
+
+    /// Copy this recfile into an owned `Recfile`
+    pub fn to_recfile(&self) -> Recfile {
+        Recfile {
+            records: self.records.iter().map(|r| r.to_record()).collect(),
+            descriptors: self.descriptors.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::RecfileRef;
+    use {ParseOptions, RecError, Recfile};
+
+    #[test]
+    fn agrees_with_recfile() {
+        let sources = [
+            include_str!("../samples/books.rec"),
+            include_str!("../samples/entries.rec"),
+            include_str!("../samples/has_default.rec"),
+            "\u{feff}# c:\\\nId: 1\r\nPath: C:\\\\\nNote:  a \\\nb\n+ c\n\n%rec: A\n\nId: 2\n",
+        ];
+        for src in sources.iter() {
+            let borrowed = RecfileRef::parse(src).unwrap().to_recfile();
+            let owned = Recfile::parse(src.as_bytes()).unwrap();
+            assert_eq!(borrowed.records, owned.records);
+            assert_eq!(borrowed.descriptors, owned.descriptors);
+            for (a, b) in borrowed.records.iter().zip(owned.records.iter()) {
+                assert_eq!(a.span, b.span);
+                assert_eq!(a.field_spans, b.field_spans);
+                assert_eq!(a.splits, b.splits);
+            }
+        }
+    }
+
+    #[test]
+    fn only_joined_values_allocate() {
+        let src = "%rec: Book\n\nTitle: Mio Cid\nPath: C:\\\\\nNote: a\n+ b\nSplit: a\\\nb\n";
+        let file = RecfileRef::parse(src).unwrap();
+        let rec = &file.records[0];
+        assert_eq!(rec.rec_type, Some(Cow::Borrowed("Book")));
+        assert!(matches!(rec.fields[0].1, Cow::Borrowed("Mio Cid")));
+        assert!(matches!(rec.fields[1].1, Cow::Borrowed("C:\\")));
+        assert!(matches!(rec.fields[2].1, Cow::Owned(_)));
+        assert_eq!(rec.get("Note").unwrap(), "a\nb");
+        assert_eq!(rec.get_all("Split"), vec!["ab"]);
+        assert_eq!(rec.get("Missing"), Err(RecError::MissingField { name: "Missing".to_owned() }));
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        match RecfileRef::parse("A: 1\n\n  oops\n") {
+            Err(e) => assert_eq!(e.to_string(), "3:3: Invalid line: oops"),
+            Ok(_) => panic!("expected an error"),
+        }
+        assert!(RecfileRef::parse("+ a\n").is_err());
+    }
+
+    #[test]
+    fn options_are_followed() {
+        let src = "Id: 1\nBad Name: x\n\noops\n\nId: 2\n";
+        let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+        assert!(matches!(RecfileRef::parse_with(src, &strict), Err(RecError::InvalidFieldName { .. })));
+
+        let lenient = ParseOptions { lenient: true, ..ParseOptions::default() };
+        let (file, warnings) = RecfileRef::parse_with(src, &lenient).unwrap();
+        assert_eq!(file.records.len(), 2);
+        assert_eq!(warnings.len(), 1);
+
+        let limited = |options: ParseOptions| match RecfileRef::parse_with(src, &options) {
+            Err(RecError::LimitExceeded { what, pos, .. }) => (what, pos.line),
+            other => panic!("unexpected result {:?}", other),
+        };
+        let lenient = |options: ParseOptions| ParseOptions { lenient: true, ..options };
+        assert_eq!(limited(lenient(ParseOptions { max_records: Some(1), ..ParseOptions::default() })),
+                   ("records".to_owned(), 6));
+        assert_eq!(limited(ParseOptions { max_fields: Some(1), ..ParseOptions::default() }),
+                   ("fields in a record".to_owned(), 2));
+        assert_eq!(limited(ParseOptions { max_line_length: Some(8), ..ParseOptions::default() }),
+                   ("bytes in a line".to_owned(), 2));
+    }
+}
@@ -0,0 +1,621 @@
+//! A lossless syntax tree for recfiles. `Recfile::parse` keeps only
+//! the records and their fields, so writing a parsed file back out
+//! loses comments, blank lines and spacing. A `Document` is read by
+//! the same parser, but also keeps the source text of every line, so
+//! that it can be written back byte-for-byte, and editing a field
+//! only rewrites the text of that field. Tools which modify files in
+//! place, like `rr-set`, make their changes through a `Document`.
+
+use std::fmt;
+
+use edit::field_text;
+use {RecError, Recfile, Record, RecordDescriptor, RecordReader, Span};
+
+/// A recfile along with all of its comments and formatting
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Document {
+    pub items: Vec<Item>,
+}
+
+/// The top-level pieces of a `Document`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Item {
+    /// Blank lines and comments between records, kept verbatim
+    Trivia(String),
+    /// A record or record descriptor
+    Record(RecordNode),
+}
+
+/// A record, which is a run of fields and the comments between them
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct RecordNode {
+    pub items: Vec<RecordItem>,
+}
+
+/// The lines of a `RecordNode`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum RecordItem {
+    /// A comment line inside a record, kept verbatim
+    Comment(String),
+    Field(FieldNode),
+}
+
+/// A single field, along with the text it was parsed from. Comments
+/// which are followed by `+` continuation lines of the field are
+/// part of its text.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct FieldNode {
+    name: String,
+    value: String,
+    text: String,
+    /// The offsets into the value where it was split across lines
+    /// with a trailing backslash
+    splits: Vec<usize>,
+}
+
+/// The line terminator used by some text, so that rewritten lines
+/// can match the lines around them
+fn line_ending(text: &str) -> &'static str {
+    if text.contains("\r\n") { "\r\n" } else { "\n" }
+}
+
+/// The offset just past the terminator of the line ending at `offset`
+fn line_end(source: &str, offset: usize) -> usize {
+    let rest = &source[offset..];
+    if rest.starts_with("\r\n") {
+        offset + 2
+    } else if rest.starts_with('\n') {
+        offset + 1
+    } else {
+        offset
+    }
+}
+
+/// Strip the terminator from the end of a line, if it has one
+fn strip_terminator(line: &str) -> &str {
+    match line.strip_suffix('\n') {
+        Some(l) => l.strip_suffix('\r').unwrap_or(l),
+        None => line,
+    }
+}
+
+impl FieldNode {
+    /// Create a new field, formatted as `Record::write` would
+    pub fn new<V: Into<String>>(name: &str, value: V) -> FieldNode {
+        let value = value.into();
+        FieldNode {
+            text: field_text(name, &value),
+            name: name.to_owned(),
+            value,
+            splits: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The source text of this field, including its line terminator
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replace the value of this field, rewriting its text
+    pub fn set_value<V: Into<String>>(&mut self, value: V) {
+        let value = value.into();
+        if value == self.value {
+            return;
+        }
+        let mut text = field_text(&self.name, &value);
+        let ending = line_ending(&self.text);
+        if ending != "\n" {
+            text = text.replace('\n', ending);
+        }
+        if !self.text.ends_with('\n') {
+            text.truncate(strip_terminator(&text).len());
+        }
+        self.text = text;
+        self.value = value;
+        self.splits.clear();
+    }
+
+    /// Change the name of this field, keeping the rest of its text
+    pub fn rename(&mut self, name: &str) {
+        let indent = self.text.len() - self.text.trim_start_matches(' ').len();
+        let rest = self.text[indent + self.name.len()..].to_owned();
+        self.text.truncate(indent);
+        self.text.push_str(name);
+        self.text.push_str(&rest);
+        self.name = name.to_owned();
+    }
+}
+
+impl RecordNode {
+    /// Iterate over the fields of this record
+    pub fn fields(&self) -> impl Iterator<Item = &FieldNode> {
+        self.items.iter().filter_map(|i| match i {
+            RecordItem::Field(f) => Some(f),
+            RecordItem::Comment(_) => None,
+        })
+    }
+
+    /// Iterate mutably over the fields of this record
+    pub fn fields_mut(&mut self) -> impl Iterator<Item = &mut FieldNode> {
+        self.items.iter_mut().filter_map(|i| match i {
+            RecordItem::Field(f) => Some(f),
+            RecordItem::Comment(_) => None,
+        })
+    }
+
+    /// Return the first value of a field
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields().find(|f| f.name == name).map(|f| f.value())
+    }
+
+    /// Return the first field with the given name
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut FieldNode> {
+        self.fields_mut().find(|f| f.name == name)
+    }
+
+    /// Set the first field with the given name, adding it to the end
+    /// of the record if there isn't one
+    pub fn set<V: Into<String>>(&mut self, name: &str, value: V) {
+        match self.field_mut(name) {
+            Some(f) => f.set_value(value),
+            None => self.push(name, value),
+        }
+    }
+
+    /// Add a field after the last field of the record
+    pub fn push<V: Into<String>>(&mut self, name: &str, value: V) {
+        let mut field = FieldNode::new(name, value);
+        let last = self.items.iter().rposition(|i| match i {
+            RecordItem::Field(_) => true,
+            RecordItem::Comment(_) => false,
+        });
+        let index = last.map_or(self.items.len(), |i| i + 1);
+        if let Some(prev) = index.checked_sub(1).map(|i| &mut self.items[i]) {
+            let (RecordItem::Field(FieldNode { text, .. }) | RecordItem::Comment(text)) = prev;
+            if !text.ends_with('\n') {
+                // the previous line ended the file, and now this one does
+                let ending = line_ending(text);
+                text.push_str(ending);
+                field.text.truncate(strip_terminator(&field.text).len());
+            } else if line_ending(text) != "\n" {
+                field.text = field.text.replace('\n', "\r\n");
+            }
+        }
+        self.items.insert(index, RecordItem::Field(field));
+    }
+
+    /// Remove the first field with the given name, returning its value
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let index = self.items.iter().position(|i| match i {
+            RecordItem::Field(f) => f.name == name,
+            RecordItem::Comment(_) => false,
+        })?;
+        match self.items.remove(index) {
+            RecordItem::Field(f) => Some(f.value),
+            RecordItem::Comment(_) => unreachable!(),
+        }
+    }
+
+    /// Rename every field called `from` to `to`, returning the number
+    /// of fields renamed
+    pub fn rename(&mut self, from: &str, to: &str) -> usize {
+        let mut count = 0;
+        for f in self.fields_mut().filter(|f| f.name == from) {
+            f.rename(to);
+            count += 1;
+        }
+        count
+    }
+
+    /// The fields of this record as a plain `Record`
+    pub fn to_record(&self) -> Record {
+        Record {
+            splits: self.fields()
+                .enumerate()
+                .flat_map(|(i, f)| f.splits.iter().map(move |&offset| (i, offset)))
+                .collect(),
+            ..Record::new(None, self.fields().map(|f| (f.name.as_str(), f.value.as_str())))
+        }
+    }
+
+    /// Whether this record is a record descriptor
+    pub fn is_descriptor(&self) -> bool {
+        self.fields().any(|f| f.name == "%rec")
+    }
+}
+
+impl Document {
+    /// Parse the source of a recfile, keeping all of its text. The
+    /// records are read by a `RecordReader`, so this accepts exactly
+    /// what `Recfile::parse` does, and the text between their fields
+    /// is kept as comments and trivia.
+    pub fn parse(source: &str) -> Result<Document, RecError> {
+        let mut doc = Document::default();
+        let mut reader = RecordReader::new(source.as_bytes());
+        // the offset of the first byte not yet in the document
+        let mut done = 0;
+        while let Some(rec) = reader.next_raw() {
+            let rec = rec?;
+            let mut node = RecordNode::default();
+            let fields = rec.fields.into_iter().zip(rec.field_spans);
+            for (i, ((name, value), span)) in fields.enumerate() {
+                let gap = &source[done..span.start_offset];
+                if i == 0 {
+                    doc.push_trivia(gap);
+                } else {
+                    // only comments can come between the fields of a
+                    // record
+                    node.items.extend(gap.split_inclusive('\n')
+                                      .map(|c| RecordItem::Comment(c.to_owned())));
+                }
+                let end = line_end(source, span.end_offset);
+                node.items.push(RecordItem::Field(FieldNode {
+                    name,
+                    value,
+                    text: source[span.start_offset..end].to_owned(),
+                    splits: rec.splits.iter()
+                        .filter(|&&(f, _)| f == i)
+                        .map(|&(_, offset)| offset)
+                        .collect(),
+                }));
+                done = end;
+            }
+            doc.items.push(Item::Record(node));
+        }
+        doc.push_trivia(&source[done..]);
+        Ok(doc)
+    }
+
+    fn push_trivia(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.items.push(Item::Trivia(text.to_owned()));
+        }
+    }
+
+    /// Write this document out exactly as it was parsed, apart from
+    /// any edits
+    pub fn write<W>(&self, w: &mut W) -> std::io::Result<()>
+        where W: std::io::Write
+    {
+        write!(w, "{}", self)
+    }
+
+    /// Iterate over the records (and descriptors) of this document
+    pub fn records(&self) -> impl Iterator<Item = &RecordNode> {
+        self.items.iter().filter_map(|i| match i {
+            Item::Record(r) => Some(r),
+            Item::Trivia(_) => None,
+        })
+    }
+
+    /// Iterate mutably over the records (and descriptors) of this
+    /// document
+    pub fn records_mut(&mut self) -> impl Iterator<Item = &mut RecordNode> {
+        self.items.iter_mut().filter_map(|i| match i {
+            Item::Record(r) => Some(r),
+            Item::Trivia(_) => None,
+        })
+    }
+
+    /// Add a record to the end of the document, separated from the
+    /// one before it by a blank line
+    pub fn push_record(&mut self, rec: &Record) {
+        let end = self.items.len();
+        self.insert_record_at(end, rec);
+    }
+
+    /// Insert a record before the `index`th item of the document. It
+    /// is separated from the text before it by a blank line, and
+    /// from the text after it too if it becomes the first record.
+    pub fn insert_record_at(&mut self, index: usize, rec: &Record) {
+        let before: String = self.items[..index].iter().map(|i| i.to_string()).collect();
+        let after: String = self.items[index..].iter().map(|i| i.to_string()).collect();
+        let ending = line_ending(&self.to_string());
+
+        let mut new = vec![];
+        if !before.is_empty() {
+            let mut sep = String::new();
+            if !before.ends_with('\n') {
+                sep.push_str(ending);
+            }
+            if !before.ends_with("\n\n") && !before.ends_with("\n\r\n") {
+                sep.push_str(ending);
+            }
+            if !sep.is_empty() {
+                new.push(Item::Trivia(sep));
+            }
+        }
+        let mut node = RecordNode::default();
+        for (k, v) in rec.fields.iter() {
+            let mut field = FieldNode::new(k, v.as_str());
+            if ending != "\n" {
+                field.text = field.text.replace('\n', ending);
+            }
+            node.items.push(RecordItem::Field(field));
+        }
+        new.push(Item::Record(node));
+        if before.is_empty() && !after.is_empty() {
+            new.push(Item::Trivia(ending.to_owned()));
+        }
+        self.items.splice(index..index, new);
+    }
+
+    /// The position in `items` of the `index`th record which isn't a
+    /// descriptor
+    fn record_position(&self, index: usize) -> Option<usize> {
+        self.items.iter()
+            .enumerate()
+            .filter(|(_, i)| match i {
+                Item::Record(r) => !r.is_descriptor(),
+                Item::Trivia(_) => false,
+            })
+            .nth(index)
+            .map(|(pos, _)| pos)
+    }
+
+    /// Remove the `index`th record of the document, counting only
+    /// records which aren't descriptors (as in `to_recfile`), along
+    /// with the blank line after it. If the record was the last thing
+    /// in the document, the blank line before it is removed instead.
+    pub fn remove_record(&mut self, index: usize) -> Option<RecordNode> {
+        let pos = self.record_position(index)?;
+        let removed = match self.items.remove(pos) {
+            Item::Record(r) => r,
+            Item::Trivia(_) => unreachable!(),
+        };
+        if let Some(Item::Trivia(t)) = self.items.get_mut(pos) {
+            let first = t.find('\n').map_or(t.len(), |i| i + 1);
+            if strip_terminator(&t[..first]).trim_start_matches(' ').is_empty() {
+                t.drain(..first);
+            }
+            if t.is_empty() {
+                self.items.remove(pos);
+            }
+        } else if pos > 0 {
+            if let Some(Item::Trivia(t)) = self.items.get_mut(pos - 1) {
+                let last = t.strip_suffix('\n').unwrap_or(t).rfind('\n').map_or(0, |i| i + 1);
+                if strip_terminator(&t[last..]).trim_start_matches(' ').is_empty() {
+                    t.truncate(last);
+                }
+                if t.is_empty() {
+                    self.items.remove(pos - 1);
+                }
+            }
+        }
+        Some(removed)
+    }
+
+    /// Turn the `index`th record of the document (counting as in
+    /// `remove_record`) into comments by prefixing its lines with
+    /// `#`. Returns `false` if there is no such record.
+    pub fn comment_out_record(&mut self, index: usize) -> bool {
+        let pos = match self.record_position(index) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        let text: String = self.items[pos].to_string()
+            .split_inclusive('\n')
+            .map(|ln| format!("#{}", ln))
+            .collect();
+        self.items[pos] = Item::Trivia(text);
+        true
+    }
+
+    /// Turn any records which have no fields left, such as ones whose
+    /// fields have all been commented out, into plain comments, so
+    /// that they aren't counted as records
+    pub fn empty_records_to_trivia(&mut self) {
+        for item in std::mem::take(&mut self.items) {
+            match item {
+                Item::Record(ref r) if r.fields().next().is_none() => self.push_trivia(&r.to_string()),
+                item => self.items.push(item),
+            }
+        }
+    }
+
+    /// Return the first descriptor for records of the given type
+    pub fn descriptor_mut(&mut self, rec_type: &str) -> Option<&mut RecordNode> {
+        self.records_mut().find(|r| {
+            r.get("%rec").and_then(|v| v.split_whitespace().next()) == Some(rec_type)
+        })
+    }
+
+    /// Interpret this document as a `Recfile`, with spans pointing at
+    /// its current text
+    pub fn to_recfile(&self) -> Result<Recfile, RecError> {
+        let mut file = Recfile { records: vec![], descriptors: vec![] };
+        let mut rec_type = None;
+        let mut line = 1;
+        let mut offset = 0;
+        // advance past some text, returning the span it covers
+        let mut advance = |text: &str| {
+            let start = Span {
+                start: line,
+                end: line + text.matches('\n').count(),
+                start_offset: offset,
+                end_offset: offset + strip_terminator(text).len(),
+            };
+            line += text.matches('\n').count();
+            offset += text.len();
+            if text.ends_with('\n') {
+                Span { end: start.end - 1, ..start }
+            } else {
+                start
+            }
+        };
+
+        for item in self.items.iter() {
+            let node = match item {
+                Item::Trivia(t) => {
+                    advance(t);
+                    continue;
+                }
+                Item::Record(r) => r,
+            };
+            let mut rec = node.to_record();
+            for i in node.items.iter() {
+                match i {
+                    RecordItem::Comment(c) => { advance(c); }
+                    RecordItem::Field(f) => {
+                        let span = advance(&f.text);
+                        Span::extend_to(&mut rec.span, span);
+                        rec.field_spans.push(span);
+                    }
+                }
+            }
+            if node.is_descriptor() {
+                let desc = RecordDescriptor::from_record(&rec)?;
+                rec_type = Some(desc.rec_type.clone());
+                file.descriptors.push(desc);
+            } else {
+                rec.rec_type = rec_type.clone();
+                file.records.push(rec);
+            }
+        }
+        Ok(file)
+    }
+}
+
+impl fmt::Display for RecordNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in self.items.iter() {
+            match i {
+                RecordItem::Field(field) => f.write_str(&field.text)?,
+                RecordItem::Comment(c) => f.write_str(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Trivia(t) => f.write_str(t),
+            Item::Record(r) => r.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in self.items.iter() {
+            item.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Document, Item};
+    use {RecError, Recfile, Record};
+
+    const SRC: &str = "# leading comment\n\n%rec: Book\n%key: Id\n\n\
+        Id: 1\n  Title:   spaced\\\n out\n# inner\n+ more\nNote: x\n\n\n\
+        # between\nId: 2\r\n# trailing\n\nId: 3";
+
+    #[test]
+    fn round_trips_exactly() {
+        let doc = Document::parse(SRC).unwrap();
+        assert_eq!(doc.to_string(), SRC);
+        let samples = [
+            include_str!("../samples/books.rec"),
+            include_str!("../samples/entries.rec"),
+            include_str!("../samples/has_default.rec"),
+        ];
+        let others = [
+            "", "\n\n", "# only\n", "A: 1\\", "A: 1\n+\n\n", "\u{feff}A: 1\r\n", "# a\\\nA: 1\\\n\n",
+        ];
+        for src in samples.iter().chain(&others) {
+            assert_eq!(Document::parse(src).unwrap().to_string(), *src);
+        }
+    }
+
+    #[test]
+    fn agrees_with_recfile() {
+        let windows = "# C:\\Temp\\\nA: C:\\Temp\\\\\nB: x\\\r\ny\n+ z\\\n\n";
+        for src in &[SRC, "\u{feff}Id: 1\r\nA: b\n", windows] {
+            let file = Document::parse(src).unwrap().to_recfile().unwrap();
+            let parsed = Recfile::parse(src.as_bytes()).unwrap();
+            assert_eq!(file.records, parsed.records);
+            assert_eq!(file.descriptors, parsed.descriptors);
+            for (a, b) in file.records.iter().zip(parsed.records.iter()) {
+                assert_eq!(a.span, b.span);
+                assert_eq!(a.field_spans, b.field_spans);
+                assert_eq!(a.splits, b.splits);
+            }
+        }
+        let file = Document::parse(SRC).unwrap().to_recfile().unwrap();
+        assert_eq!(file.records[0].get("Title").unwrap(), "  spaced out\nmore");
+    }
+
+    #[test]
+    fn edits_touch_only_their_nodes() {
+        let mut doc = Document::parse(SRC).unwrap();
+        {
+            let rec = doc.records_mut().nth(1).unwrap();
+            rec.set("Note", "y");
+            rec.rename("Title", "Name");
+            rec.push("Extra", "a\nb");
+            assert_eq!(rec.remove("Id"), Some("1".to_owned()));
+        }
+        doc.records_mut().nth(2).unwrap().set("Id", "20");
+        doc.records_mut().nth(3).unwrap().push("Done", "yes");
+        assert_eq!(doc.to_string(), "# leading comment\n\n%rec: Book\n%key: Id\n\n  \
+            Name:   spaced\\\n out\n# inner\n+ more\nNote: y\nExtra: a\n+ b\n\n\n\
+            # between\nId: 20\r\n# trailing\n\nId: 3\nDone: yes");
+    }
+
+    #[test]
+    fn adding_and_removing_records() {
+        let mut doc = Document::parse("A: 1\n\n# two\nA: 2\n\nA: 3\n").unwrap();
+        assert!(doc.remove_record(1).is_some());
+        assert_eq!(doc.to_string(), "A: 1\n\n# two\nA: 3\n");
+
+        let mut rec = Record::default();
+        rec.push("B", "x");
+        doc.push_record(&rec);
+        assert_eq!(doc.to_string(), "A: 1\n\n# two\nA: 3\n\nB: x\n");
+        assert!(doc.items.iter().all(|i| i != &Item::Trivia(String::new())));
+
+        // descriptors aren't counted, as in `to_recfile`
+        let mut doc = Document::parse("%rec: A\n\nA: 1\n\nA: 2\n+ 3").unwrap();
+        assert!(doc.comment_out_record(1));
+        assert!(!doc.comment_out_record(1));
+        assert_eq!(doc.to_string(), "%rec: A\n\nA: 1\n\n#A: 2\n#+ 3");
+        assert_eq!(doc.remove_record(0).and_then(|r| r.get("A").map(|v| v.to_owned())),
+                   Some("1".to_owned()));
+        assert_eq!(doc.to_string(), "%rec: A\n\n#A: 2\n#+ 3");
+        assert!(doc.descriptor_mut("A").is_some());
+
+        // removing the last record doesn't leave a blank line behind
+        let mut doc = Document::parse("A: 1\n\n# two\nA: 2\n\nA: 3\r\n\r\nA: 4").unwrap();
+        doc.remove_record(3);
+        assert_eq!(doc.to_string(), "A: 1\n\n# two\nA: 2\n\nA: 3\r\n");
+        doc.remove_record(2);
+        assert_eq!(doc.to_string(), "A: 1\n\n# two\nA: 2\n");
+        doc.remove_record(1);
+        assert_eq!(doc.to_string(), "A: 1\n\n# two\n");
+        assert!(doc.descriptor_mut("B").is_none());
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        match Document::parse("A: 1\n\n  oops\n") {
+            Err(RecError::InvalidLine { ln, pos }) => {
+                assert_eq!(ln, "oops");
+                assert_eq!((pos.line, pos.column, pos.offset), (3, 3, 8));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
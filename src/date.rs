@@ -0,0 +1,264 @@
+//! A small parser for the date formats that commonly show up in
+//! recfiles. Dates are turned into a number of seconds since the
+//! Unix epoch (in UTC) so that they can be compared and sorted.
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun",
+    "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// The largest year we accept, which keeps the day and second counts
+/// well within the range of an `i64`
+const MAX_YEAR: i64 = 1_000_000;
+
+fn month_from_name(s: &str) -> Option<i64> {
+    let s = s.to_lowercase();
+    if s.len() < 3 {
+        return None;
+    }
+    MONTHS.iter()
+        .position(|m| s.starts_with(m))
+        .map(|p| p as i64 + 1)
+}
+
+fn is_weekday(s: &str) -> bool {
+    let s = s.to_lowercase();
+    s.len() >= 3 && WEEKDAYS.iter().any(|d| s.starts_with(d))
+}
+
+fn number(s: &str) -> Option<i64> {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// The number of days between the epoch and the given civil date,
+/// using the algorithm from Howard Hinnant's `days_from_civil`
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn days_in_month(y: i64, m: i64) -> i64 {
+    match m {
+        2 if (y % 4 == 0 && y % 100 != 0) || y % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parse a `HH:MM[:SS[.frac]]` time, possibly followed by a `Z` or a
+/// `+HH:MM` offset, into seconds-since-midnight and an offset in
+/// seconds
+fn parse_time(s: &str) -> Option<(i64, Option<i64>)> {
+    let (time, zone) = match s.find(['Z', 'z', '+', '-']) {
+        Some(pos) => (&s[..pos], Some(&s[pos..])),
+        None => (s, None),
+    };
+    let mut parts = time.split(':');
+    let h = number(parts.next()?)?;
+    let m = number(parts.next()?)?;
+    let sec = match parts.next() {
+        Some(sec) => number(sec.split('.').next()?)?,
+        None => 0,
+    };
+    if parts.next().is_some() || h > 23 || m > 59 || sec > 60 {
+        return None;
+    }
+    let offset = match zone {
+        Some(z) => Some(parse_zone(z)?),
+        None => None,
+    };
+    Some((h * 3600 + m * 60 + sec, offset))
+}
+
+/// Parse a `Z`, `UTC`, `+HH:MM` or `-HHMM` timezone into an offset in
+/// seconds east of UTC
+fn parse_zone(s: &str) -> Option<i64> {
+    match s.to_uppercase().as_ref() {
+        "Z" | "UTC" | "GMT" => return Some(0),
+        _ => (),
+    }
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = s[1..].chars().filter(|&c| c != ':').collect();
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let h = number(&digits[..2])?;
+    let m = number(&digits[2..])?;
+    Some(sign * (h * 3600 + m * 60))
+}
+
+/// Parse a date into seconds since the Unix epoch. This understands
+/// ISO 8601 dates and datetimes (`2011-04-20`, `2011-04-20T10:30:00Z`),
+/// slash-separated dates (`2011/04/20`, `04/20/2011`), and dates with
+/// month names (`20 April 2011`, `April 20, 2011`, `13-Aug-2012`),
+/// optionally followed by a time and timezone.
+pub fn parse(s: &str) -> Option<i64> {
+    let mut year = None;
+    let mut month = None;
+    let mut day = None;
+    let mut time = 0;
+    let mut offset = None;
+
+    // an ISO datetime glues the time on with a `T`, so split that
+    // off before tokenizing
+    let s = s.trim();
+    let s = if s.len() > 10 && s.is_char_boundary(10) && s.is_char_boundary(11)
+        && &s[10..11] == "T" {
+        format!("{} {}", &s[..10], &s[11..])
+    } else {
+        s.to_owned()
+    };
+
+    for tok in s.split(|c: char| c.is_whitespace() || c == ',') {
+        if tok.is_empty() {
+            continue;
+        }
+
+        if tok.contains(':') {
+            let (t, o) = parse_time(tok)?;
+            time = t;
+            if o.is_some() {
+                offset = o;
+            }
+        } else if tok.starts_with('+') || (tok.starts_with('-') && tok.len() == 5)
+            || tok.eq_ignore_ascii_case("utc") || tok.eq_ignore_ascii_case("gmt")
+            || tok == "Z" {
+            offset = Some(parse_zone(tok)?);
+        } else if tok.contains('-') || tok.contains('/') {
+            let parts: Vec<&str> = tok.split(['-', '/']).collect();
+            if parts.len() != 3 {
+                return None;
+            }
+            if parts[0].len() == 4 {
+                // year-month-day
+                year = Some(number(parts[0])?);
+                month = Some(number(parts[1]).or_else(|| month_from_name(parts[1]))?);
+                day = Some(number(parts[2])?);
+            } else if tok.contains('/') && number(parts[1]).is_some() {
+                // month/day/year
+                month = Some(number(parts[0])?);
+                day = Some(number(parts[1])?);
+                year = Some(number(parts[2])?);
+            } else {
+                // day-month-year
+                day = Some(number(parts[0])?);
+                month = Some(number(parts[1]).or_else(|| month_from_name(parts[1]))?);
+                year = Some(number(parts[2])?);
+            }
+        } else if let Some(n) = number(tok) {
+            if tok.len() == 4 || n > 31 || day.is_some() {
+                if year.is_some() {
+                    return None;
+                }
+                year = Some(n);
+            } else {
+                day = Some(n);
+            }
+        } else if let Some(m) = month_from_name(tok.trim_end_matches('.')) {
+            month = Some(m);
+        } else if !is_weekday(tok) {
+            return None;
+        }
+    }
+
+    let (y, m, d) = (year?, month?, day?);
+    if y > MAX_YEAR || !(1..=12).contains(&m) || d < 1 || d > days_in_month(y, m) {
+        return None;
+    }
+
+    Some(days_from_civil(y, m, d) * 86_400 + time - offset.unwrap_or(0))
+}
+
+/// The civil date for a number of days since the epoch, using the
+/// algorithm from Howard Hinnant's `civil_from_days`
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+/// Format seconds since the Unix epoch as an ISO 8601 UTC datetime,
+/// e.g. `2011-04-20T10:30:00Z`
+pub fn format(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let time = secs.rem_euclid(86_400);
+    let (y, m, d) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            y, m, d, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, parse};
+
+    #[test]
+    fn iso_dates() {
+        assert_eq!(parse("1970-01-01"), Some(0));
+        assert_eq!(parse("1970-01-02"), Some(86_400));
+        assert_eq!(parse("2000-03-01"), Some(951_868_800));
+        assert_eq!(parse("1970-01-01T01:00:00Z"), Some(3_600));
+        assert_eq!(parse("1970-01-01T01:00:00+01:00"), Some(0));
+        assert_eq!(parse("1970-01-01 00:01"), Some(60));
+    }
+
+    #[test]
+    fn named_months() {
+        let d = parse("2011-04-20");
+        assert!(d.is_some());
+        assert_eq!(parse("20 April 2011"), d);
+        assert_eq!(parse("April 20, 2011"), d);
+        assert_eq!(parse("20-Apr-2011"), d);
+        assert_eq!(parse("Wed, 20 Apr 2011"), d);
+        assert_eq!(parse("24-Sept-2012"), parse("2012-09-24"));
+    }
+
+    #[test]
+    fn slash_dates() {
+        assert_eq!(parse("2011/04/20"), parse("2011-04-20"));
+        assert_eq!(parse("04/20/2011"), parse("2011-04-20"));
+    }
+
+    #[test]
+    fn format_round_trip() {
+        assert_eq!(format(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format(-1), "1969-12-31T23:59:59Z");
+        let d = parse("2000-02-29T12:34:56Z").unwrap();
+        assert_eq!(format(d), "2000-02-29T12:34:56Z");
+        assert_eq!(parse(&format(d)), Some(d));
+    }
+
+    #[test]
+    fn invalid_dates() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse("2011-02-30"), None);
+        assert_eq!(parse("2011-13-01"), None);
+        assert_eq!(parse("20 April"), None);
+        assert_eq!(parse("2011-04-20 10:30+1\u{e9}1"), None);
+        assert_eq!(parse("2011-04-20 +1\u{e9}1"), None);
+        assert_eq!(parse("1 Jan 99999999999999"), None);
+        assert_eq!(parse("99999999999999-01-01"), None);
+    }
+}
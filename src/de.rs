@@ -0,0 +1,461 @@
+//! Deserializing Rust values out of `Record`s with serde. A record
+//! is treated as a map from field names to values: repeated fields
+//! can be read into a `Vec`, absent fields into an `Option`, and the
+//! type of the record appears as an extra field named `%rec`.
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Book {
+//!     #[serde(rename = "Title")]
+//!     title: String,
+//!     #[serde(rename = "Author", default)]
+//!     authors: Vec<String>,
+//! }
+//!
+//! let books: Vec<Book> = recfile.iter_by_type("Book")
+//!     .map(rrecutils::from_record)
+//!     .collect::<Result<_, _>>()?;
+//! ```
+
+use std::convert::TryFrom;
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::Deserialize;
+
+use types::{parse_bool, parse_int, parse_real};
+use {RecError, Record};
+
+/// The name under which the type of a record is deserialized
+pub const TYPE_FIELD: &str = "%rec";
+
+/// serde requires its errors to implement `std::error::Error`, which
+/// `RecError` can't do alongside `Fail`, so this wraps it while
+/// serializing or deserializing
+#[derive(Debug)]
+pub struct Error(pub RecError);
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Error {
+        Error(RecError::ConversionError { message: msg.to_string() })
+    }
+
+    fn missing_field(field: &'static str) -> Error {
+        Error(RecError::MissingField { name: field.to_owned() })
+    }
+}
+
+/// Deserialize a value out of a record
+pub fn from_record<'de, T: Deserialize<'de>>(rec: &'de Record) -> Result<T, RecError> {
+    T::deserialize(RecordDeserializer::new(rec)).map_err(|Error(e)| e)
+}
+
+/// A serde `Deserializer` which presents a record as a map
+pub struct RecordDeserializer<'de> {
+    /// The values of each field, grouped by name in the order that
+    /// each name first appears
+    fields: Vec<(&'de str, Vec<&'de str>)>,
+}
+
+impl<'de> RecordDeserializer<'de> {
+    pub fn new(rec: &'de Record) -> RecordDeserializer<'de> {
+        let mut fields: Vec<(&str, Vec<&str>)> = vec![];
+        if let Some(ref t) = rec.rec_type {
+            fields.push((TYPE_FIELD, vec![t]));
+        }
+        for (k, v) in rec.fields.iter() {
+            match fields.iter_mut().find(|(n, _)| n == k) {
+                Some(field) => field.1.push(v),
+                None => fields.push((k, vec![v])),
+            }
+        }
+        RecordDeserializer { fields }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for RecordDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(FieldMap { fields: self.fields.into_iter(), next: None })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct FieldMap<'de> {
+    fields: std::vec::IntoIter<(&'de str, Vec<&'de str>)>,
+    next: Option<(&'de str, Vec<&'de str>)>,
+}
+
+impl<'de> de::MapAccess<'de> for FieldMap<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.fields.next() {
+            Some(field) => {
+                let name = field.0;
+                self.next = Some(field);
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.next.take() {
+            Some((name, values)) => seed.deserialize(FieldDeserializer { name, values }),
+            None => Err(de::Error::custom("value requested before key")),
+        }
+    }
+}
+
+/// Deserializes every value of one field: a sequence reads all of
+/// them, and anything else requires there to be exactly one
+struct FieldDeserializer<'de> {
+    name: &'de str,
+    values: Vec<&'de str>,
+}
+
+impl<'de> FieldDeserializer<'de> {
+    fn single(self) -> Result<ValueDeserializer<'de>, Error> {
+        match self.values[..] {
+            [value] => Ok(ValueDeserializer { name: self.name, value }),
+            _ => Err(Error(RecError::ConversionError {
+                message: format!("field {} appears {} times but only one value is expected",
+                                 self.name, self.values.len()),
+            })),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    }
+}
+
+impl<'de> de::Deserializer<'de> for FieldDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.values.len() == 1 {
+            self.single()?.deserialize_any(visitor)
+        } else {
+            self.deserialize_seq(visitor)
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let name = self.name;
+        let values = self.values.into_iter().map(|value| ValueDeserializer { name, value });
+        visitor.visit_seq(de::value::SeqDeserializer::new(values))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_unit_struct(name, visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_u8 deserialize_u16 deserialize_u32
+        deserialize_u64 deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_unit deserialize_identifier
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 tuple tuple_struct map struct ignored_any
+    }
+}
+
+/// Deserializes a single field value, parsing it according to the
+/// type that is asked for
+struct ValueDeserializer<'de> {
+    name: &'de str,
+    value: &'de str,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn invalid(&self, expected: &str) -> Error {
+        Error(RecError::InvalidValue {
+            name: self.name.to_owned(),
+            value: self.value.to_owned(),
+            expected: expected.to_owned(),
+        })
+    }
+
+    fn int<T: TryFrom<i64>>(&self, expected: &str) -> Result<T, Error> {
+        parse_int(self.value)
+            .and_then(|n| T::try_from(n).ok())
+            .ok_or_else(|| self.invalid(expected))
+    }
+
+    fn real(&self) -> Result<f64, Error> {
+        parse_real(self.value).ok_or_else(|| self.invalid("real"))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_int {
+    ($($method:ident => $visit:ident $typ:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.int::<$typ>(stringify!($typ))?)
+            }
+        )*
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.value)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_bool(parse_bool(self.value).ok_or_else(|| self.invalid("bool"))?)
+    }
+
+    deserialize_int! {
+        deserialize_i8 => visit_i8 i8,
+        deserialize_i16 => visit_i16 i16,
+        deserialize_i32 => visit_i32 i32,
+        deserialize_i64 => visit_i64 i64,
+        deserialize_u8 => visit_u8 u8,
+        deserialize_u16 => visit_u16 u16,
+        deserialize_u32 => visit_u32 u32,
+        deserialize_u64 => visit_u64 u64,
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f32(self.real()? as f32)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.real()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut chars = self.value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(self.invalid("char")),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_bytes(self.value.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.value.trim().into_deserializer())
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(self.invalid("sequence"))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(self.invalid("map"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        Err(self.invalid(name))
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 str string tuple tuple_struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_record;
+    use {RecError, Recfile};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Location {
+        #[serde(rename = "home")]
+        Home,
+        #[serde(rename = "loaned")]
+        Loaned,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Book {
+        #[serde(rename = "%rec")]
+        rec_type: Option<String>,
+        #[serde(rename = "Title")]
+        title: String,
+        #[serde(rename = "Author", default)]
+        authors: Vec<String>,
+        #[serde(rename = "Year")]
+        year: Option<u32>,
+        #[serde(rename = "Location")]
+        location: Location,
+        #[serde(rename = "Read", default)]
+        read: bool,
+    }
+
+    fn books() -> Recfile {
+        Recfile::parse(&b"%rec: Book
+
+Title: GNU Emacs Manual
+Author: Richard M. Stallman
+Author: Others
+Location: home
+Read: yes
+
+Title: Mio Cid
+Year: 1200
+Location: loaned
+Publisher: unknown
+"[..]).unwrap()
+    }
+
+    #[test]
+    fn structs() {
+        let file = books();
+        let books: Vec<Book> = file.iter_by_type("Book")
+            .map(from_record)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(books, vec![
+            Book {
+                rec_type: Some("Book".to_owned()),
+                title: "GNU Emacs Manual".to_owned(),
+                authors: vec!["Richard M. Stallman".to_owned(), "Others".to_owned()],
+                year: None,
+                location: Location::Home,
+                read: true,
+            },
+            Book {
+                rec_type: Some("Book".to_owned()),
+                title: "Mio Cid".to_owned(),
+                authors: vec![],
+                year: Some(1200),
+                location: Location::Loaned,
+                read: false,
+            },
+        ]);
+    }
+
+    #[test]
+    fn maps() {
+        let file = books();
+        let map: ::std::collections::BTreeMap<String, String> =
+            from_record(&file.records[1]).unwrap();
+        assert_eq!(map["Publisher"], "unknown");
+        assert_eq!(map["%rec"], "Book");
+    }
+
+    #[test]
+    fn errors() {
+        #[derive(Deserialize, Debug)]
+        struct Strict {
+            #[serde(rename = "Title")]
+            _title: String,
+            #[serde(rename = "Year")]
+            _year: u8,
+        }
+
+        let file = books();
+        assert_eq!(from_record::<Strict>(&file.records[0]).unwrap_err(),
+                   RecError::MissingField { name: "Year".to_owned() });
+        assert_eq!(from_record::<Strict>(&file.records[1]).unwrap_err(),
+                   RecError::InvalidValue {
+                       name: "Year".to_owned(),
+                       value: "1200".to_owned(),
+                       expected: "u8".to_owned(),
+                   });
+        assert!(from_record::<Book>(&file.records[0]).is_ok());
+        assert!(from_record::<(String, String)>(&file.records[0]).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use {Position, RecError, Record, Span};
+
+/// The comparison used by a `%size` constraint
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum SizeCondition {
+    Exactly,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+}
+
+/// A `%size` constraint, which limits the number of records of a
+/// given type that may appear in a file
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SizeConstraint {
+    pub condition: SizeCondition,
+    pub count: usize,
+}
+
+impl SizeConstraint {
+    /// Parse the value of a `%size` field, e.g. `3` or `<= 10`
+    pub fn parse(s: &str) -> Result<SizeConstraint, RecError> {
+        let s = s.trim();
+        let (condition, rest) = if let Some(r) = s.strip_prefix("<=") {
+            (SizeCondition::LessOrEqual, r)
+        } else if let Some(r) = s.strip_prefix(">=") {
+            (SizeCondition::GreaterOrEqual, r)
+        } else if let Some(r) = s.strip_prefix('<') {
+            (SizeCondition::LessThan, r)
+        } else if let Some(r) = s.strip_prefix('>') {
+            (SizeCondition::GreaterThan, r)
+        } else {
+            (SizeCondition::Exactly, s)
+        };
+        let count = rest.trim().parse()
+            .map_err(|_| invalid(format!("bad %size value: {}", s)))?;
+        Ok(SizeConstraint { condition, count })
+    }
+
+    /// Returns `true` if a record set of size `n` satisfies this
+    /// constraint
+    pub fn allows(&self, n: usize) -> bool {
+        match self.condition {
+            SizeCondition::Exactly => n == self.count,
+            SizeCondition::LessThan => n < self.count,
+            SizeCondition::LessOrEqual => n <= self.count,
+            SizeCondition::GreaterThan => n > self.count,
+            SizeCondition::GreaterOrEqual => n >= self.count,
+        }
+    }
+}
+
+impl std::fmt::Display for SizeConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let op = match self.condition {
+            SizeCondition::Exactly => "",
+            SizeCondition::LessThan => "< ",
+            SizeCondition::LessOrEqual => "<= ",
+            SizeCondition::GreaterThan => "> ",
+            SizeCondition::GreaterOrEqual => ">= ",
+        };
+        write!(f, "{}{}", op, self.count)
+    }
+}
+
+/// A `RecordDescriptor` is the schema for a set of records, taken
+/// from the special `%`-prefixed fields of a `%rec` block. Fields
+/// which can appear more than once (like `%mandatory`) are
+/// accumulated in the order they appear.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct RecordDescriptor {
+    /// The type name given by `%rec`
+    pub rec_type: String,
+    /// The optional URL or path following the type name in `%rec`
+    pub url: Option<String>,
+    pub key: Option<String>,
+    pub mandatory: Vec<String>,
+    pub allowed: Vec<String>,
+    pub prohibit: Vec<String>,
+    pub unique: Vec<String>,
+    /// Pairs of field names and the (unparsed) type descriptions
+    /// given to them by `%type`
+    pub types: Vec<(String, String)>,
+    /// Pairs of type names and the (unparsed) type descriptions
+    /// given to them by `%typedef`
+    pub typedefs: Vec<(String, String)>,
+    pub auto: Vec<String>,
+    pub sort: Vec<String>,
+    pub size: Option<SizeConstraint>,
+    /// The (unparsed) selection expressions given by `%constraint`
+    pub constraints: Vec<String>,
+    pub confidential: Vec<String>,
+    pub doc: Option<String>,
+    /// Any other special fields that we don't interpret
+    pub other: Vec<(String, String)>,
+    /// The location of the `%rec` block in its source file, if it
+    /// was parsed from one
+    pub span: Option<Span>,
+}
+
+/// An `InvalidDescriptor` error, which is given a position once we
+/// know which descriptor it is about
+fn invalid(message: String) -> RecError {
+    RecError::InvalidDescriptor { message, pos: Position::default() }
+}
+
+fn field_list(val: &str) -> Vec<String> {
+    val.split_whitespace().map(|s| s.to_owned()).collect()
+}
+
+fn split_first_word(val: &str) -> (&str, &str) {
+    let val = val.trim();
+    match val.find(char::is_whitespace) {
+        Some(pos) => (&val[..pos], val[pos..].trim()),
+        None => (val, ""),
+    }
+}
+
+impl RecordDescriptor {
+    /// Build a descriptor out of a record containing a `%rec` field
+    pub fn from_record(rec: &Record) -> Result<RecordDescriptor, RecError> {
+        RecordDescriptor::read_fields(rec).map_err(|e| e.in_descriptor(rec.span))
+    }
+
+    fn read_fields(rec: &Record) -> Result<RecordDescriptor, RecError> {
+        let mut desc = RecordDescriptor {
+            span: rec.span,
+            ..RecordDescriptor::default()
+        };
+        let mut seen_rec = false;
+
+        for (name, val) in rec.fields.iter() {
+            match name.as_ref() {
+                "%rec" => {
+                    if seen_rec {
+                        return Err(invalid("more than one %rec field".to_owned()));
+                    }
+                    seen_rec = true;
+                    let (typ, url) = split_first_word(val);
+                    desc.rec_type = typ.to_owned();
+                    if !url.is_empty() {
+                        desc.url = Some(url.to_owned());
+                    }
+                }
+                "%key" => {
+                    if desc.key.is_some() {
+                        return Err(invalid("more than one %key field".to_owned()));
+                    }
+                    desc.key = Some(val.trim().to_owned());
+                }
+                "%mandatory" => desc.mandatory.extend(field_list(val)),
+                "%allowed" => desc.allowed.extend(field_list(val)),
+                "%prohibit" => desc.prohibit.extend(field_list(val)),
+                "%unique" => desc.unique.extend(field_list(val)),
+                "%auto" => desc.auto.extend(field_list(val)),
+                "%sort" => desc.sort.extend(field_list(val)),
+                "%confidential" => desc.confidential.extend(field_list(val)),
+                "%type" => {
+                    let (fields, typ) = split_first_word(val);
+                    for f in fields.split(',').filter(|f| !f.is_empty()) {
+                        desc.types.push((f.to_owned(), typ.to_owned()));
+                    }
+                }
+                "%typedef" => {
+                    let (name, typ) = split_first_word(val);
+                    desc.typedefs.push((name.to_owned(), typ.to_owned()));
+                }
+                "%size" => {
+                    if desc.size.is_some() {
+                        return Err(invalid("more than one %size field".to_owned()));
+                    }
+                    desc.size = Some(SizeConstraint::parse(val)?);
+                }
+                "%constraint" => desc.constraints.push(val.trim().to_owned()),
+                "%doc" => desc.doc = Some(val.clone()),
+                _ => desc.other.push((name.clone(), val.clone())),
+            }
+        }
+
+        if !seen_rec || desc.rec_type.is_empty() {
+            return Err(invalid("descriptor without a %rec type".to_owned()));
+        }
+
+        Ok(desc)
+    }
+
+    /// Turn this descriptor back into a `Record` of `%`-fields. This
+    /// produces the fields in a canonical order, and so will not
+    /// necessarily match the layout of the original source.
+    pub fn to_record(&self) -> Record {
+        fn list(fields: &mut Vec<(String, String)>, name: &str, vals: &[String]) {
+            if !vals.is_empty() {
+                fields.push((name.to_owned(), vals.join(" ")));
+            }
+        }
+
+        let mut fields = vec![];
+        let rec = match self.url {
+            Some(ref url) => format!("{} {}", self.rec_type, url),
+            None => self.rec_type.clone(),
+        };
+        fields.push(("%rec".to_owned(), rec));
+        if let Some(ref doc) = self.doc {
+            fields.push(("%doc".to_owned(), doc.clone()));
+        }
+        if let Some(ref key) = self.key {
+            fields.push(("%key".to_owned(), key.clone()));
+        }
+        list(&mut fields, "%mandatory", &self.mandatory);
+        list(&mut fields, "%allowed", &self.allowed);
+        list(&mut fields, "%prohibit", &self.prohibit);
+        list(&mut fields, "%unique", &self.unique);
+        for (name, typ) in self.typedefs.iter() {
+            fields.push(("%typedef".to_owned(), format!("{} {}", name, typ)));
+        }
+        for (name, typ) in self.types.iter() {
+            fields.push(("%type".to_owned(), format!("{} {}", name, typ)));
+        }
+        list(&mut fields, "%auto", &self.auto);
+        list(&mut fields, "%sort", &self.sort);
+        if let Some(ref size) = self.size {
+            fields.push(("%size".to_owned(), size.to_string()));
+        }
+        for c in self.constraints.iter() {
+            fields.push(("%constraint".to_owned(), c.clone()));
+        }
+        list(&mut fields, "%confidential", &self.confidential);
+        fields.extend(self.other.iter().cloned());
+
+        Record { span: self.span, ..Record::new(None, fields) }
+    }
+
+    /// Write the serialized version of this descriptor to the
+    /// provided `Write`r
+    pub fn write<W>(&self, w: &mut W) -> std::io::Result<()>
+        where W: std::io::Write
+    {
+        self.to_record().write(w)
+    }
+
+    /// Return the (unparsed) type description declared for a field
+    /// by `%type`, if any
+    pub fn type_of(&self, field: &str) -> Option<&str> {
+        self.types.iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, t)| t.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecordDescriptor, SizeCondition, SizeConstraint};
+    use Record;
+
+    #[test]
+    fn field_lists_accumulate() {
+        let d = RecordDescriptor::from_record(&Record::new(None, vec![
+            ("%rec", "Book"),
+            ("%mandatory", "Title"),
+            ("%mandatory", "Author Location"),
+        ])).unwrap();
+        assert_eq!(d.rec_type, "Book");
+        assert_eq!(d.mandatory, vec!["Title", "Author", "Location"]);
+    }
+
+    #[test]
+    fn types_split_field_list() {
+        let d = RecordDescriptor::from_record(&Record::new(None, vec![
+            ("%rec", "Item"),
+            ("%type", "Id,Count int"),
+            ("%type", "Location enum loaned home unknown"),
+        ])).unwrap();
+        assert_eq!(d.type_of("Id"), Some("int"));
+        assert_eq!(d.type_of("Count"), Some("int"));
+        assert_eq!(d.type_of("Location"), Some("enum loaned home unknown"));
+        assert_eq!(d.type_of("Title"), None);
+    }
+
+    #[test]
+    fn rec_with_url() {
+        let rec = Record::new(None, vec![("%rec", "Contact contacts.rec")]);
+        let d = RecordDescriptor::from_record(&rec).unwrap();
+        assert_eq!(d.rec_type, "Contact");
+        assert_eq!(d.url, Some("contacts.rec".to_owned()));
+    }
+
+    #[test]
+    fn size_constraints() {
+        let s = SizeConstraint::parse("<= 3").unwrap();
+        assert_eq!(s.condition, SizeCondition::LessOrEqual);
+        assert!(s.allows(3));
+        assert!(!s.allows(4));
+        assert!(SizeConstraint::parse("2").unwrap().allows(2));
+        assert!(SizeConstraint::parse("lots").is_err());
+    }
+
+    #[test]
+    fn missing_rec_is_an_error() {
+        assert!(RecordDescriptor::from_record(&Record::new(None, vec![("%key", "Id")])).is_err());
+    }
+
+    #[test]
+    fn round_trip_through_record() {
+        let d = RecordDescriptor::from_record(&Record::new(None, vec![
+            ("%rec", "Book"),
+            ("%key", "Id"),
+            ("%type", "Id int"),
+            ("%size", "> 1"),
+            ("%doc", "Some books"),
+        ])).unwrap();
+        assert_eq!(RecordDescriptor::from_record(&d.to_record()).unwrap(), d);
+    }
+}
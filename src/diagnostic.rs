@@ -0,0 +1,112 @@
+//! Diagnostics describe problems found while reading a recfile in a
+//! form suitable for editors and CI reports, where all of the
+//! problems in a file should be shown at once.
+
+use std::fmt;
+
+use {Position, RecError, Span};
+
+/// How serious a problem is
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    /// Input which could not be read, and was skipped
+    Error,
+    /// Input which was read, but not exactly as written
+    Warning,
+}
+
+/// A problem with a recfile, along with where it was found
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The part of the input the problem is in. This is empty when
+    /// the problem is at a single point.
+    pub span: Span,
+    /// Where the problem starts, including the file name and column
+    /// where they are known
+    pub pos: Option<Position>,
+    /// A description of the problem, without its position
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: Span, message: String) -> Diagnostic {
+        Diagnostic { severity, span, pos: None, message }
+    }
+
+    /// Describe an error at the position it happened, if it has one
+    pub fn from_error(severity: Severity, err: &RecError) -> Diagnostic {
+        let text = err.to_string();
+        // the error's own text starts with its position, which is
+        // kept apart here so that it can be shown first
+        let message = match err.position() {
+            Some(pos) => text.strip_prefix(&format!("{}: ", pos)).unwrap_or(&text).to_owned(),
+            None => text,
+        };
+        Diagnostic {
+            severity,
+            span: err.position().map(Span::at).unwrap_or_default(),
+            pos: err.position().cloned(),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref pos) = self.pos {
+            write!(f, "{}: ", pos)?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Severity;
+    use {Recfile, Span};
+
+    #[test]
+    fn recovers_at_blank_lines() {
+        let src = &b"Id: 1\n\nId: 2\nbad line\nName: x\n\n+ stray\n\n%rec: A\n%size: lots\n\n\
+            Id: 3\nName: Jos\xe9\n\nId: 4\n"[..];
+        let (file, diags) = Recfile::parse_recovering(src);
+        let ids: Vec<&str> = file.records.iter().map(|r| r.get("Id").unwrap()).collect();
+        assert_eq!(ids, vec!["1", "4"]);
+        assert!(file.descriptors.is_empty());
+
+        assert_eq!(diags.len(), 4);
+        assert!(diags.iter().all(|d| d.severity == Severity::Error));
+        assert_eq!(diags[0].span, Span { start: 4, end: 4, start_offset: 13, end_offset: 21 });
+        assert_eq!(diags[0].to_string(), "4:1: error: Invalid line: bad line");
+        assert_eq!(diags[1].span.start, 7);
+        assert_eq!(diags[2].span, Span { start: 9, end: 10, start_offset: 40, end_offset: 59 });
+        assert!(diags[2].message.contains("bad %size value"));
+        assert_eq!(diags[3].span.start, 13);
+    }
+
+    #[test]
+    fn stops_at_fatal_errors() {
+        use reader::{ParseOptions, RecordReader};
+
+        let options = ParseOptions { max_records: Some(1), lossy: true, ..ParseOptions::default() };
+        let mut reader = RecordReader::new(&b"Id: 1\nName: Jos\xe9\n\nId: 2\n\nId: 3\n"[..])
+            .with_options(&options)
+            .recovering();
+        assert_eq!(reader.by_ref().count(), 1);
+        let diags = reader.diagnostics();
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[1].severity, Severity::Error);
+        assert_eq!(diags[1].span.start, 4);
+    }
+}
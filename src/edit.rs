@@ -0,0 +1,242 @@
+//! Edits to the records of a `Document`, as made by tools which
+//! modify recfiles in place. Only the fields which change are
+//! rewritten, so comments and formatting elsewhere are kept.
+
+use cst::{RecordItem, RecordNode};
+
+/// A change to one field of a record
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum FieldOp {
+    /// Change the value of every instance of the field
+    Set(String),
+    /// Like `Set`, but add the field if the record doesn't have it
+    SetOrAdd(String),
+    /// Add another instance of the field at the end of the record
+    Add(String),
+    /// Give every instance of the field a new name
+    Rename(String),
+    /// Remove every instance of the field
+    Delete,
+    /// Turn every instance of the field into a comment
+    Comment,
+}
+
+/// Format a field, writing any lines after the first as `+`
+/// continuation lines
+pub fn field_text(name: &str, value: &str) -> String {
+    field_text_with_splits(name, value, &[])
+}
+
+/// Format a field as `field_text` does, also splitting its lines with
+/// a trailing backslash at each of the given offsets into the value
+pub fn field_text_with_splits(name: &str, value: &str, splits: &[usize]) -> String {
+    let mut splits = splits.to_vec();
+    splits.sort();
+    let mut text = String::new();
+    let mut start = 0;
+    for (i, ln) in value.split('\n').enumerate() {
+        let end = start + ln.len();
+        let mut line = String::new();
+        let mut pos = start;
+        for &split in splits.iter().filter(|&&s| s >= start && s <= end) {
+            if !value.is_char_boundary(split) {
+                continue;
+            }
+            // a backslash before a split would be read as an escape
+            let piece = &value[pos..split];
+            if piece.ends_with('\\') {
+                continue;
+            }
+            line.push_str(piece);
+            line.push_str("\\\n");
+            pos = split;
+        }
+        let rest = &value[pos..end];
+        line.push_str(rest);
+        if rest.ends_with('\\') {
+            line.push('\\');
+        }
+
+        if i == 0 {
+            text.push_str(&format!("{}: {}\n", name, line));
+        } else if line.is_empty() {
+            text.push_str("+\n");
+        } else {
+            text.push_str(&format!("+ {}\n", line));
+        }
+        start = end + 1;
+    }
+    text
+}
+
+/// The descriptor fields which hold a list of field names
+const FIELD_LISTS: [&str; 8] = [
+    "%key", "%mandatory", "%allowed", "%prohibit", "%unique", "%auto", "%sort", "%confidential",
+];
+
+impl RecordNode {
+    /// Apply a `FieldOp` to every instance of a field in this record.
+    /// Other fields and comments in the record are kept exactly as
+    /// they were. Returns `true` if the record changed.
+    pub fn edit_field(&mut self, field: &str, op: &FieldOp) -> bool {
+        let before = self.to_string();
+        let found = self.fields().any(|f| f.name() == field);
+        match *op {
+            FieldOp::Set(ref v) | FieldOp::SetOrAdd(ref v) if found => {
+                for f in self.fields_mut().filter(|f| f.name() == field) {
+                    f.set_value(v.as_str());
+                }
+            }
+            FieldOp::Set(_) => (),
+            FieldOp::SetOrAdd(ref v) | FieldOp::Add(ref v) => self.push(field, v.as_str()),
+            FieldOp::Rename(ref new) => {
+                self.rename(field, new);
+            }
+            FieldOp::Delete => self.items.retain(|i| match i {
+                RecordItem::Field(f) => f.name() != field,
+                RecordItem::Comment(_) => true,
+            }),
+            FieldOp::Comment => {
+                let items = std::mem::take(&mut self.items);
+                for i in items {
+                    match i {
+                        RecordItem::Field(ref f) if f.name() == field => {
+                            self.items.extend(f.text().split_inclusive('\n')
+                                              .map(|ln| RecordItem::Comment(format!("#{}", ln))));
+                        }
+                        i => self.items.push(i),
+                    }
+                }
+            }
+        }
+        self.to_string() != before
+    }
+
+    /// Update this descriptor after a field has been renamed or
+    /// removed, changing the field lists of `%mandatory`, `%type`,
+    /// `%key` and the like to match. Returns `false` if the
+    /// descriptor doesn't mention the field or the operation doesn't
+    /// affect descriptors.
+    pub fn edit_descriptor_field(&mut self, field: &str, op: &FieldOp) -> bool {
+        let replacement = match *op {
+            FieldOp::Rename(ref new) => Some(new.as_str()),
+            FieldOp::Delete => None,
+            _ => return false,
+        };
+        let update = |names: Vec<&str>| -> Vec<String> {
+            names.into_iter()
+                .filter_map(|n| if n == field { replacement } else { Some(n) })
+                .map(|n| n.to_owned())
+                .collect()
+        };
+
+        let mut changed = false;
+        let mut emptied = vec![];
+        for (i, item) in self.items.iter_mut().enumerate() {
+            let f = match item {
+                RecordItem::Field(f) => f,
+                RecordItem::Comment(_) => continue,
+            };
+            let value = f.value();
+            let new_value = if FIELD_LISTS.contains(&f.name()) {
+                let names: Vec<&str> = value.split_whitespace().collect();
+                if names.contains(&field) {
+                    Some(update(names).join(" "))
+                } else {
+                    None
+                }
+            } else if f.name() == "%type" {
+                let trimmed = value.trim();
+                let (fields, typ) = trimmed.split_at(trimmed.find(char::is_whitespace).unwrap_or(trimmed.len()));
+                let names: Vec<&str> = fields.split(',').collect();
+                if names.contains(&field) {
+                    let names = update(names);
+                    if names.is_empty() {
+                        Some(String::new())
+                    } else {
+                        Some(format!("{}{}", names.join(","), typ))
+                    }
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+
+            match new_value {
+                Some(ref v) if v.trim().is_empty() => emptied.push(i),
+                Some(v) => f.set_value(v),
+                None => continue,
+            }
+            changed = true;
+        }
+        for i in emptied.into_iter().rev() {
+            self.items.remove(i);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FieldOp;
+    use cst::Document;
+
+    fn edit(src: &str, op: FieldOp) -> String {
+        let mut doc = Document::parse(src).unwrap();
+        doc.records_mut().next().unwrap().edit_field("a", &op);
+        doc.to_string()
+    }
+
+    #[test]
+    fn field_edits() {
+        let src = "a: 1\n+ 2\n# note\nb: x \\\ny\na: 3\n";
+        assert_eq!(edit(src, FieldOp::Set("4\n5".to_owned())),
+                   "a: 4\n+ 5\n# note\nb: x \\\ny\na: 4\n+ 5\n");
+        assert_eq!(edit(src, FieldOp::Rename("c".to_owned())),
+                   "c: 1\n+ 2\n# note\nb: x \\\ny\nc: 3\n");
+        assert_eq!(edit(src, FieldOp::Delete), "# note\nb: x \\\ny\n");
+        assert_eq!(edit(src, FieldOp::Comment),
+                   "#a: 1\n#+ 2\n# note\nb: x \\\ny\n#a: 3\n");
+        assert_eq!(edit("b: 1", FieldOp::Add("2".to_owned())), "b: 1\na: 2");
+        assert_eq!(edit("b: 1\n", FieldOp::SetOrAdd("2".to_owned())), "b: 1\na: 2\n");
+        assert_eq!(edit("a: 2\n", FieldOp::SetOrAdd("2".to_owned())), "a: 2\n");
+        assert_eq!(edit("b: 2\n", FieldOp::Set("2".to_owned())), "b: 2\n");
+        // a backslash only joins lines inside values
+        assert_eq!(edit("# C:\\\na: 1\\\\\na: 2\n", FieldOp::Delete), "# C:\\\n");
+    }
+
+    #[test]
+    fn descriptor_edits() {
+        let src = "%rec: A\n%mandatory: a b\n%type: a,c int\n%type: a line\n%doc: x\n";
+        let rename = |op: &FieldOp| {
+            let mut doc = Document::parse(src).unwrap();
+            let changed = doc.descriptor_mut("A").unwrap().edit_descriptor_field("a", op);
+            (changed, doc.to_string())
+        };
+        assert_eq!(rename(&FieldOp::Rename("z".to_owned())),
+                   (true, "%rec: A\n%mandatory: z b\n%type: z,c int\n%type: z line\n%doc: x\n".to_owned()));
+        assert_eq!(rename(&FieldOp::Delete),
+                   (true, "%rec: A\n%mandatory: b\n%type: c int\n%doc: x\n".to_owned()));
+        let mut doc = Document::parse(src).unwrap();
+        assert!(!doc.descriptor_mut("A").unwrap().edit_descriptor_field("q", &FieldOp::Delete));
+    }
+
+    #[test]
+    fn records_without_fields_become_comments() {
+        for op in [FieldOp::Comment, FieldOp::Delete].iter() {
+            let mut doc = Document::parse("a: 1\n\na: 2\n# c\n\na: 3\n").unwrap();
+            assert!(doc.records_mut().nth(1).unwrap().edit_field("a", op));
+            doc.empty_records_to_trivia();
+            let file = doc.to_recfile().unwrap();
+            let values: Vec<&str> = file.records.iter().map(|r| r.get("a").unwrap()).collect();
+            assert_eq!(values, vec!["1", "3"]);
+            assert_eq!(doc.records().count(), 2);
+        }
+        let mut doc = Document::parse("a: 1\n\na: 2\n").unwrap();
+        doc.records_mut().nth(1).unwrap().edit_field("a", &FieldOp::Comment);
+        doc.empty_records_to_trivia();
+        assert_eq!(doc.to_string(), "a: 1\n\n#a: 2\n");
+        assert!(doc.remove_record(1).is_none());
+    }
+}
@@ -0,0 +1,338 @@
+//! Field expressions, which use the same syntax as the `-p` option
+//! of GNU `recsel`: for example, `Title,Author[0-1]:Authors` or
+//! `Location,Count(Title):Books`.
+
+use reader::valid_field_name;
+use sex::Value;
+use types::{parse_int, parse_real};
+use {RecError, Record};
+
+/// The aggregate functions that can be applied to a field
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn from_name(name: &str) -> Option<Aggregate> {
+        match name.to_lowercase().as_ref() {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "avg" => Some(Aggregate::Avg),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Aggregate::Count => "Count",
+            Aggregate::Sum => "Sum",
+            Aggregate::Avg => "Avg",
+            Aggregate::Min => "Min",
+            Aggregate::Max => "Max",
+        }
+    }
+
+    /// Apply this function to a list of values. Values which aren't
+    /// numbers are ignored by everything but `Count`, and the
+    /// numeric functions produce zero when there are no numbers.
+    pub fn apply<S: AsRef<str>>(&self, values: &[S]) -> Value {
+        if *self == Aggregate::Count {
+            return Value::Int(values.len() as i64);
+        }
+
+        let ints: Vec<i64> = values.iter().filter_map(|v| parse_int(v.as_ref())).collect();
+        let reals: Vec<f64> = values.iter()
+            .filter_map(|v| parse_int(v.as_ref())
+                        .map(|n| n as f64)
+                        .or_else(|| parse_real(v.as_ref())))
+            .collect();
+        let all_ints = ints.len() == reals.len();
+
+        if reals.is_empty() {
+            return Value::Int(0);
+        }
+
+        match *self {
+            Aggregate::Count => unreachable!(),
+            Aggregate::Sum if all_ints =>
+                Value::Int(ints.iter().fold(0i64, |a, &b| a.wrapping_add(b))),
+            Aggregate::Sum => Value::Real(reals.iter().sum()),
+            Aggregate::Avg => Value::Real(reals.iter().sum::<f64>() / reals.len() as f64),
+            Aggregate::Min if all_ints => Value::Int(*ints.iter().min().unwrap()),
+            Aggregate::Max if all_ints => Value::Int(*ints.iter().max().unwrap()),
+            Aggregate::Min => Value::Real(reals.iter().cloned().fold(f64::INFINITY, f64::min)),
+            Aggregate::Max => Value::Real(reals.iter().cloned().fold(f64::NEG_INFINITY, f64::max)),
+        }
+    }
+
+    /// Apply this function to every value of the named field across
+    /// a set of records, e.g. `Aggregate::Count.over("Title",
+    /// recfile.iter_by_type("Book"))`
+    pub fn over<'a, I>(&self, field: &str, recs: I) -> Value
+        where I: IntoIterator<Item = &'a Record>
+    {
+        let values: Vec<&str> = recs.into_iter()
+            .flat_map(|r| r.get_all(field))
+            .collect();
+        self.apply(&values)
+    }
+}
+
+/// A single element of a field expression, selecting some or all of
+/// the values of one field
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct FexElem {
+    pub field: String,
+    /// The inclusive range of values to select, counting from zero.
+    /// `Field[n]` is the range `(n, n)`, and a bare field name selects
+    /// every value.
+    pub range: Option<(usize, usize)>,
+    /// The name to give the selected fields in the output
+    pub rename: Option<String>,
+    /// The function to summarize the selected values with, if any
+    pub function: Option<Aggregate>,
+}
+
+/// A `FieldExpr` is a list of fields to project out of a record
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct FieldExpr {
+    pub elems: Vec<FexElem>,
+}
+
+fn bad_fex(message: String) -> RecError {
+    RecError::InvalidExpression { message }
+}
+
+fn field_name(s: &str) -> Result<String, RecError> {
+    if valid_field_name(s) {
+        Ok(s.to_owned())
+    } else {
+        Err(bad_fex(format!("bad field name: {}", s)))
+    }
+}
+
+fn index(s: &str) -> Result<usize, RecError> {
+    s.trim().parse().map_err(|_| bad_fex(format!("bad subscript: {}", s)))
+}
+
+impl FexElem {
+    fn parse(s: &str) -> Result<FexElem, RecError> {
+        let (body, rename) = match s.find(':') {
+            Some(pos) => (&s[..pos], Some(field_name(s[pos + 1..].trim())?)),
+            None => (s, None),
+        };
+        let body = body.trim();
+
+        // an aggregate function wraps the rest of the element, as in
+        // `Count(Author[0-1])`
+        let (function, body) = match body.find('(') {
+            Some(pos) if body.ends_with(')') => {
+                let name = body[..pos].trim();
+                let function = Aggregate::from_name(name)
+                    .ok_or_else(|| bad_fex(format!("unknown function: {}", name)))?;
+                (Some(function), body[pos + 1..body.len() - 1].trim())
+            }
+            _ => (None, body),
+        };
+
+        let (field, range) = match body.find('[') {
+            Some(pos) => {
+                let sub = body[pos + 1..]
+                    .strip_suffix(']')
+                    .ok_or_else(|| bad_fex(format!("unterminated subscript: {}", body)))?;
+                let range = match sub.find('-') {
+                    Some(dash) => (index(&sub[..dash])?, index(&sub[dash + 1..])?),
+                    None => (index(sub)?, index(sub)?),
+                };
+                if range.0 > range.1 {
+                    return Err(bad_fex(format!("bad range: {}", body)));
+                }
+                (&body[..pos], Some(range))
+            }
+            None => (body, None),
+        };
+
+        Ok(FexElem { field: field_name(field.trim())?, range, rename, function })
+    }
+
+    /// The name that fields selected by this element are given. An
+    /// aggregate which isn't renamed is called e.g. `Count_Title`.
+    pub fn output_name(&self) -> String {
+        match (&self.rename, self.function) {
+            (Some(r), _) => r.clone(),
+            (None, Some(f)) => format!("{}_{}", f.name(), self.field),
+            (None, None) => self.field.clone(),
+        }
+    }
+
+    /// The values of the record selected by this element
+    pub fn values<'a>(&self, rec: &'a Record) -> Vec<&'a str> {
+        rec.get_all(&self.field)
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| match self.range {
+                Some((lo, hi)) => lo <= i && i <= hi,
+                None => true,
+            })
+            .map(|(_, v)| v)
+            .collect()
+    }
+}
+
+impl FieldExpr {
+    /// Parse a comma-separated field expression
+    pub fn parse(s: &str) -> Result<FieldExpr, RecError> {
+        let elems = s.split(',')
+            .map(FexElem::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(FieldExpr { elems })
+    }
+
+    /// Returns `true` if any element uses an aggregate function
+    pub fn has_aggregates(&self) -> bool {
+        self.elems.iter().any(|e| e.function.is_some())
+    }
+
+    /// Build a new record out of the fields selected by this
+    /// expression, in the order the expression lists them. Aggregate
+    /// functions are applied to the values within this one record.
+    pub fn project(&self, rec: &Record) -> Record {
+        self.aggregate(Some(rec))
+    }
+
+    /// Build a single record summarizing a set of records: aggregate
+    /// functions are applied to the values across all of the records,
+    /// while plain fields are taken from the first record.
+    pub fn aggregate<'a, I>(&self, recs: I) -> Record
+        where I: IntoIterator<Item = &'a Record>
+    {
+        let recs: Vec<&Record> = recs.into_iter().collect();
+        let mut fields = vec![];
+        for e in self.elems.iter() {
+            match e.function {
+                Some(f) => {
+                    let values: Vec<&str> = recs.iter().flat_map(|r| e.values(r)).collect();
+                    fields.push((e.output_name(), f.apply(&values).to_string()));
+                }
+                None => if let Some(first) = recs.first() {
+                    for v in e.values(first) {
+                        fields.push((e.output_name(), v.to_owned()));
+                    }
+                },
+            }
+        }
+        let rec_type = recs.first().and_then(|r| r.rec_type.as_deref());
+        Record {
+            span: if recs.len() == 1 { recs[0].span } else { None },
+            ..Record::new(rec_type, fields)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aggregate, FexElem, FieldExpr};
+use sex::Value;
+    use {Recfile, Record};
+
+    fn book() -> Record {
+        Record::new(Some("Book"), vec![
+            ("Title", "chapters.gnu.org administration guide"),
+            ("Author", "Nacho Gonzalez"),
+            ("Author", "Jose E. Marchesi"),
+            ("Location", "unknown"),
+        ])
+    }
+
+    fn project(fex: &str) -> Vec<(String, String)> {
+        FieldExpr::parse(fex).unwrap().project(&book()).fields
+    }
+
+    #[test]
+    fn parse_elements() {
+        assert_eq!(FieldExpr::parse("Title, Author[1-2]:Writer").unwrap().elems, vec![
+            FexElem { field: "Title".to_owned(), range: None, rename: None, function: None },
+            FexElem {
+                field: "Author".to_owned(),
+                range: Some((1, 2)),
+                rename: Some("Writer".to_owned()),
+                function: None,
+            },
+        ]);
+        assert!(FieldExpr::parse("").is_err());
+        assert!(FieldExpr::parse("Title[").is_err());
+        assert!(FieldExpr::parse("Title[2-1]").is_err());
+        assert!(FieldExpr::parse("Title:").is_err());
+        assert!(FieldExpr::parse("Ti tle").is_err());
+    }
+
+    #[test]
+    fn projection() {
+        assert_eq!(project("Location,Title"), vec![
+            ("Location".to_owned(), "unknown".to_owned()),
+            ("Title".to_owned(), "chapters.gnu.org administration guide".to_owned()),
+        ]);
+        assert_eq!(project("Author").len(), 2);
+        assert_eq!(project("Author[1]"), vec![
+            ("Author".to_owned(), "Jose E. Marchesi".to_owned()),
+        ]);
+        assert_eq!(project("Author[0-5]:Name").len(), 2);
+        assert_eq!(project("Author[0-5]:Name")[0].0, "Name");
+        assert!(project("Publisher").is_empty());
+    }
+
+    #[test]
+    fn parse_aggregates() {
+        let fex = FieldExpr::parse("Count(Author[1-2]):N,Max(Pages)").unwrap();
+        assert!(fex.has_aggregates());
+        assert_eq!(fex.elems[0].function, Some(Aggregate::Count));
+        assert_eq!(fex.elems[0].range, Some((1, 2)));
+        assert_eq!(fex.elems[0].output_name(), "N");
+        assert_eq!(fex.elems[1].output_name(), "Max_Pages");
+        assert!(FieldExpr::parse("Median(Pages)").is_err());
+        assert!(!FieldExpr::parse("Title").unwrap().has_aggregates());
+    }
+
+    #[test]
+    fn apply_aggregates() {
+        let vals = ["3", "1", "x", "2"];
+        assert_eq!(Aggregate::Count.apply(&vals), Value::Int(4));
+        assert_eq!(Aggregate::Sum.apply(&vals), Value::Int(6));
+        assert_eq!(Aggregate::Avg.apply(&vals), Value::Real(2.0));
+        assert_eq!(Aggregate::Min.apply(&vals), Value::Int(1));
+        assert_eq!(Aggregate::Max.apply(&["1.5", "2"]), Value::Real(2.0));
+        assert_eq!(Aggregate::Sum.apply(&["1.5", "2"]), Value::Real(3.5));
+        assert_eq!(Aggregate::Max.apply::<&str>(&[]), Value::Int(0));
+    }
+
+    #[test]
+    fn aggregate_records() {
+        let file = Recfile::parse(&b"%rec: Book
+
+Title: a
+Pages: 10
+
+Title: b
+Pages: 30
+"[..]).unwrap();
+        assert_eq!(Aggregate::Count.over("Title", file.iter_by_type("Book")), Value::Int(2));
+        assert_eq!(Aggregate::Avg.over("Pages", file.iter()), Value::Real(20.0));
+        let summary = FieldExpr::parse("Count(Title),Sum(Pages):Total")
+            .unwrap()
+            .aggregate(file.iter());
+        assert_eq!(summary.fields, vec![
+            ("Count_Title".to_owned(), "2".to_owned()),
+            ("Total".to_owned(), "40".to_owned()),
+        ]);
+        assert_eq!(
+            FieldExpr::parse("Count(Author)").unwrap().project(&book()).fields,
+            vec![("Count_Author".to_owned(), "2".to_owned())]);
+    }
+}
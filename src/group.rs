@@ -0,0 +1,123 @@
+use std::cmp::Ordering;
+
+use {Recfile, Record, SortKey};
+
+impl Recfile {
+    /// Merge together the records of each type which have the same
+    /// values for all of the named fields, as `recsel -G` does. Values
+    /// are compared using the fields' declared types. The
+    /// records are first stably sorted by those fields, and then each
+    /// group becomes a single record holding the fields of its first
+    /// record, followed by the fields of the rest of the group
+    /// except for the grouping fields themselves. The records of a
+    /// group are merged in the order they were in before grouping.
+    pub fn group_by(&mut self, fields: &[String]) {
+        if fields.is_empty() {
+            return;
+        }
+
+        let keys: Vec<SortKey> = fields.iter()
+            .map(|f| SortKey { field: f.clone(), descending: false })
+            .collect();
+        self.sort_by_keys(&keys);
+
+        let mut grouped: Vec<Record> = vec![];
+        for rec in std::mem::take(&mut self.records) {
+            if let Some(last) = grouped.last_mut() {
+                // compare as the sort did, so that values which sort
+                // together (like `1` and `01` in an `int` field) are
+                // grouped together. A missing field only matches
+                // another missing field, not an empty one.
+                let desc = self.descriptor_for(&rec);
+                if last.rec_type == rec.rec_type
+                    && keys.iter().all(|k| k.compare(desc, last, &rec) == Ordering::Equal)
+                {
+                    for (k, v) in rec.fields.into_iter().filter(|(k, _)| !fields.contains(k)) {
+                        last.push(&k, v);
+                    }
+                    continue;
+                }
+            }
+            grouped.push(rec);
+        }
+        self.records = grouped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Recfile, SortKey};
+
+    #[test]
+    fn group_by_location() {
+        let mut file = Recfile::parse(&b"%rec: Book
+
+Title: a
+Location: home
+
+Title: b
+Location: loaned
+
+Title: c
+Location: home
+Author: x
+"[..]).unwrap();
+        file.group_by(&["Location".to_owned()]);
+        assert_eq!(file.to_string().unwrap(), "%rec: Book
+
+Title: a
+Location: home
+Title: c
+Author: x
+
+Title: b
+Location: loaned
+
+");
+    }
+
+    #[test]
+    fn group_by_several_fields() {
+        let mut file = Recfile::parse(
+            &b"A: 1\nB: 1\nC: x\n\nA: 1\nB: 2\nC: y\n\nA: 1\nB: 1\nC: z\n"[..],
+        ).unwrap();
+        file.group_by(&["A".to_owned(), "B".to_owned()]);
+        assert_eq!(file.records.len(), 2);
+        assert_eq!(file.records[0].fields.len(), 4);
+        assert_eq!(file.records[1].get("C").unwrap(), "y");
+    }
+
+    #[test]
+    fn typed_and_missing_keys() {
+        let mut file = Recfile::parse(&b"%rec: A
+%type: N int
+
+N: 1
+T: a
+
+N:
+T: b
+
+N: 2
+T: c
+
+N: 01
+T: d
+
+T: e
+"[..]).unwrap();
+        file.group_by(&["N".to_owned()]);
+        let groups: Vec<Vec<&str>> = file.records.iter().map(|r| r.get_all("T")).collect();
+        assert_eq!(groups, vec![vec!["a", "d"], vec!["c"], vec!["b"], vec!["e"]]);
+    }
+
+    #[test]
+    fn groups_keep_the_order_of_their_records() {
+        let mut file = Recfile::parse(&b"L: b\nT: 1\n\nL: a\nT: 2\n\nL: b\nT: 3\n"[..]).unwrap();
+        file.sort_by_keys(&SortKey::parse_list("-T"));
+        file.group_by(&["L".to_owned()]);
+        assert_eq!(file.records[1].get_all("T"), vec!["3", "1"]);
+        file.sort_by_keys(&SortKey::parse_list("-T"));
+        assert_eq!(file.records[0].get("L").unwrap(), "b");
+    }
+}
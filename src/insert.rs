@@ -0,0 +1,171 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use cst::{Document, Item};
+use types::parse_int;
+use {date, FieldType, Recfile, Record};
+
+/// A random number which is good enough for generating UUIDs, taken
+/// from the randomly-seeded hasher in the standard library
+fn random_u64() -> u64 {
+    let mut h = RandomState::new().build_hasher();
+    if let Ok(d) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        h.write_u128(d.as_nanos());
+    }
+    h.finish()
+}
+
+/// Generate a random (version 4) UUID
+fn random_uuid() -> String {
+    let (a, b) = (random_u64(), random_u64());
+    let b = (b & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+    format!("{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+            a >> 32, (a >> 16) & 0xffff, a & 0xfff,
+            b >> 48, b & 0xffff_ffff_ffff)
+}
+
+fn now() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    date::format(secs)
+}
+
+impl Recfile {
+    /// Generate values for the `%auto` fields of a record which it
+    /// doesn't already have, adding them to the start of the record.
+    /// Integer fields (and fields with no declared type) count up from
+    /// the largest existing value, `uuid` fields get a random UUID,
+    /// and `date` fields get the current time.
+    pub fn fill_auto(&self, rec: &mut Record) {
+        let desc = match self.descriptor_for(rec) {
+            Some(d) => d,
+            None => return,
+        };
+
+        let mut added = 0;
+        for field in desc.auto.iter() {
+            if rec.get(field).is_ok() {
+                continue;
+            }
+            let value = match desc.field_type(field) {
+                Ok(Some(FieldType::Uuid)) => random_uuid(),
+                Ok(Some(FieldType::Date)) => now(),
+                _ => {
+                    let max = self.iter_by_type(&desc.rec_type)
+                        .flat_map(|r| r.get_all(field))
+                        .filter_map(parse_int)
+                        .max();
+                    match max {
+                        Some(n) => (n + 1).to_string(),
+                        None => "0".to_owned(),
+                    }
+                }
+            };
+            rec.insert_at(added, field, value);
+            added += 1;
+        }
+    }
+}
+
+impl Document {
+    /// Insert a record into the document, placing it after the last
+    /// record of the same type (or after the descriptor, if there are
+    /// none yet). A record with a type but no descriptor is added at
+    /// the end of the document along with a new descriptor, and a
+    /// record without a type is added after the other untyped records.
+    pub fn insert_record(&mut self, rec: &Record) {
+        // the type of the records at each point of the document
+        let mut rec_type: Option<&str> = None;
+        let mut last_record = None;
+        let mut descriptor = None;
+        for (i, item) in self.items.iter().enumerate() {
+            let node = match item {
+                Item::Record(node) => node,
+                Item::Trivia(_) => continue,
+            };
+            if node.is_descriptor() {
+                rec_type = node.get("%rec").and_then(|v| v.split_whitespace().next());
+                if rec_type == rec.rec_type.as_deref() && descriptor.is_none() {
+                    descriptor = Some(i);
+                }
+            } else if rec_type == rec.rec_type.as_deref() {
+                last_record = Some(i);
+            }
+        }
+
+        match (&rec.rec_type, last_record.or(descriptor)) {
+            (_, Some(i)) => self.insert_record_at(i + 1, rec),
+            (Some(t), None) => {
+                let mut desc = Record::default();
+                desc.push("%rec", t.as_str());
+                self.push_record(&desc);
+                self.push_record(rec);
+            }
+            (None, None) => self.insert_record_at(0, rec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cst::Document;
+    use {Recfile, Record};
+
+    fn insert(src: &str, rec: Record) -> String {
+        let mut doc = Document::parse(src).unwrap();
+        doc.insert_record(&rec);
+        doc.to_string()
+    }
+
+    #[test]
+    fn auto_fields() {
+        let file = Recfile::parse(&b"%rec: A
+%auto: Id Uid
+%type: Uid uuid
+
+Id: 4
+Uid: f81d4fae-7dec-11d0-a765-00a0c91e6bf6
+"[..]).unwrap();
+        let mut rec = Record::new(Some("A"), vec![("Name", "x")]);
+        file.fill_auto(&mut rec);
+        assert_eq!(rec.fields[0], ("Id".to_owned(), "5".to_owned()));
+        assert_eq!(rec.fields[1].0, "Uid");
+        assert_eq!(rec.fields[1].1.len(), 36);
+        assert_eq!(rec.fields[2].0, "Name");
+
+        let mut given = Record::new(Some("A"), vec![("Id", "10")]);
+        file.fill_auto(&mut given);
+        assert_eq!(given.get("Id").unwrap(), "10");
+    }
+
+    #[test]
+    fn insert_into_section() {
+        assert_eq!(
+            insert("%rec: A\n\nId: 1\n# c\n\n%rec: B\n\nId: 2\n",
+                   Record::new(Some("A"), vec![("Id", "3")])),
+            "%rec: A\n\nId: 1\n\nId: 3\n# c\n\n%rec: B\n\nId: 2\n");
+        assert_eq!(
+            insert("%rec: A\n\n%rec: B\n", Record::new(Some("A"), vec![("Id", "3")])),
+            "%rec: A\n\nId: 3\n\n%rec: B\n");
+    }
+
+    #[test]
+    fn insert_new_type() {
+        assert_eq!(
+            insert("%rec: A\n\nId: 1", Record::new(Some("B"), vec![("Id", "3")])),
+            "%rec: A\n\nId: 1\n\n%rec: B\n\nId: 3\n");
+    }
+
+    #[test]
+    fn insert_untyped() {
+        assert_eq!(
+            insert("# header\n%rec: A\n", Record::new(None, vec![("Id", "3")])),
+            "Id: 3\n\n# header\n%rec: A\n");
+        assert_eq!(
+            insert("Id: 1\n\n%rec: A\n", Record::new(None, vec![("Id", "3")])),
+            "Id: 1\n\nId: 3\n\n%rec: A\n");
+        assert_eq!(insert("", Record::new(None, vec![("Id", "3")])), "Id: 3\n");
+    }
+}
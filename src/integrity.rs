@@ -0,0 +1,261 @@
+use {Expr, RecError, Recfile, Record, RecordDescriptor, Span};
+
+/// A `Violation` is a single way in which a `Recfile` fails to
+/// conform to its record descriptors
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Violation {
+    /// The type of the offending record, if it has one
+    pub rec_type: Option<String>,
+    /// The location of the offending record or descriptor
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.rec_type {
+            Some(ref t) => write!(f, "{}: {}", t, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+fn violation(desc: &RecordDescriptor, span: Option<Span>, message: String) -> Violation {
+    Violation {
+        rec_type: Some(desc.rec_type.clone()),
+        span,
+        message,
+    }
+}
+
+fn count(rec: &Record, field: &str) -> usize {
+    rec.get_all(field).len()
+}
+
+/// Check the problems that can be found within a single record
+fn check_record(desc: &RecordDescriptor, rec: &Record, out: &mut Vec<Violation>) {
+    // problems with a particular field point at that field if we know
+    // where it is, and at the whole record otherwise
+    let field_span = |name: &str| rec.field_span(name).or(rec.span);
+    let mut report = |span, message| out.push(violation(desc, span, message));
+
+    for f in desc.mandatory.iter() {
+        if count(rec, f) == 0 {
+            report(rec.span, format!("mandatory field {} is missing", f));
+        }
+    }
+
+    for f in desc.prohibit.iter() {
+        if count(rec, f) > 0 {
+            report(field_span(f), format!("prohibited field {} is present", f));
+        }
+    }
+
+    // as in GNU recutils, `%unique` fields may appear at most once in
+    // each record
+    for f in desc.unique.iter() {
+        if count(rec, f) > 1 {
+            report(field_span(f), format!("unique field {} appears more than once", f));
+        }
+    }
+
+    if let Some(ref key) = desc.key {
+        match count(rec, key) {
+            0 => report(rec.span, format!("key field {} is missing", key)),
+            1 => (),
+            _ => report(field_span(key), format!("key field {} appears more than once", key)),
+        }
+    }
+
+    if !desc.allowed.is_empty() {
+        for (i, (k, _)) in rec.fields.iter().enumerate() {
+            let allowed = desc.allowed.contains(k)
+                || desc.mandatory.contains(k)
+                || desc.key.as_ref() == Some(k);
+            if !allowed {
+                let span = rec.field_spans.get(i).cloned().or(rec.span);
+                report(span, format!("field {} is not allowed", k));
+            }
+        }
+    }
+
+    // bad type descriptions are reported once for the whole set
+    for err in desc.type_errors(rec) {
+        if let RecError::TypeMismatch { ref name, .. } = err {
+            report(field_span(name), err.to_string());
+        }
+    }
+}
+
+/// Check the problems that involve all the records of a type at once
+fn check_record_set(desc: &RecordDescriptor, recs: &[&Record], out: &mut Vec<Violation>) {
+    for (field, typ) in desc.types.iter() {
+        if let Err(err) = desc.field_type(field) {
+            // the violation is already placed at the descriptor
+            let message = match err {
+                RecError::InvalidDescriptor { message, .. } => message,
+                err => err.to_string(),
+            };
+            out.push(violation(desc, desc.span, format!("{} (for {} {})", message, field, typ)));
+        }
+    }
+
+    if let Some(ref size) = desc.size {
+        if !size.allows(recs.len()) {
+            out.push(violation(desc, desc.span, format!(
+                "expected {} records but found {}", size, recs.len())));
+        }
+    }
+
+    if let Some(ref key) = desc.key {
+        let mut seen: Vec<&str> = vec![];
+        for rec in recs.iter() {
+            if let Ok(val) = rec.get(key) {
+                if seen.contains(&val) {
+                    out.push(violation(desc, rec.field_span(key).or(rec.span), format!(
+                        "duplicate value for key field {}: {}", key, val)));
+                } else {
+                    seen.push(val);
+                }
+            }
+        }
+    }
+}
+
+/// Parse the `%constraint` expressions of a descriptor, reporting
+/// the ones which are not valid expressions
+fn parse_constraints<'a>(
+    desc: &'a RecordDescriptor,
+    out: &mut Vec<Violation>,
+) -> Vec<(&'a str, Expr)> {
+    let mut exprs = vec![];
+    for c in desc.constraints.iter() {
+        match Expr::parse(c) {
+            Ok(e) => exprs.push((c.as_str(), e)),
+            Err(err) => out.push(violation(desc, desc.span, format!("{} (in %constraint {})", err, c))),
+        }
+    }
+    exprs
+}
+
+fn check_constraints(
+    desc: &RecordDescriptor,
+    constraints: &[(&str, Expr)],
+    rec: &Record,
+    out: &mut Vec<Violation>,
+) {
+    for (src, expr) in constraints.iter() {
+        match expr.matches(rec) {
+            Ok(true) => (),
+            Ok(false) => out.push(violation(desc, rec.span, format!("constraint {} failed", src))),
+            Err(err) => out.push(violation(desc, rec.span, format!("{} (in %constraint {})", err, src))),
+        }
+    }
+}
+
+impl Recfile {
+    /// Check every record against the descriptor for its type,
+    /// returning all of the violations found in source order
+    pub fn check_integrity(&self) -> Vec<Violation> {
+        let mut out = vec![];
+
+        for desc in self.descriptors.iter() {
+            let recs: Vec<&Record> = self.iter_by_type(&desc.rec_type).collect();
+            check_record_set(desc, &recs, &mut out);
+            let constraints = parse_constraints(desc, &mut out);
+            for rec in recs {
+                check_record(desc, rec, &mut out);
+                check_constraints(desc, &constraints, rec, &mut out);
+            }
+        }
+
+        out.sort_by_key(|v| v.span.map(|s| s.start));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use Recfile;
+
+    fn messages(input: &str) -> Vec<String> {
+        Recfile::parse(input.as_bytes())
+            .unwrap()
+            .check_integrity()
+            .iter()
+            .map(|v| v.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn valid_file() {
+        assert!(messages("%rec: A\n%mandatory: Id\n\nId: 1\n").is_empty());
+    }
+
+    #[test]
+    fn mandatory_and_prohibited() {
+        assert_eq!(
+            messages("%rec: A\n%mandatory: Id\n%prohibit: Secret\n\nSecret: x\n"),
+            vec![
+                "mandatory field Id is missing",
+                "prohibited field Secret is present",
+            ]);
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        assert_eq!(
+            messages("%rec: A\n%key: Id\n\nId: 1\n\nId: 2\n\nId: 1\n"),
+            vec!["duplicate value for key field Id: 1"]);
+    }
+
+    #[test]
+    fn unique_fields() {
+        assert_eq!(
+            messages("%rec: A\n%unique: Name\n\nName: a\nName: b\n\nName: a\n"),
+            vec!["unique field Name appears more than once"]);
+    }
+
+    #[test]
+    fn allowed_fields() {
+        assert_eq!(
+            messages("%rec: A\n%allowed: Name\n%mandatory: Id\n\nId: 1\nName: a\nAge: 3\n"),
+            vec!["field Age is not allowed"]);
+    }
+
+    #[test]
+    fn sizes_and_types() {
+        assert_eq!(
+            messages("%rec: A\n%size: < 2\n%type: Id int\n\nId: 1\n\nId: x\n"),
+            vec![
+                "expected < 2 records but found 2",
+                "Value of field Id is not a valid int: x",
+            ]);
+    }
+
+    #[test]
+    fn violations_have_spans() {
+        let file = Recfile::parse(&b"%rec: A\n%mandatory: Id\n\nId: 1\n\nName: a\n"[..])
+            .unwrap();
+        let v = file.check_integrity();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].span.map(|s| s.start), Some(6));
+
+        // violations involving one field point at that field
+        let file = Recfile::parse(&b"%rec: A\n%allowed: Id\n\nId: 1\nAge: 3\nId: 2\n"[..])
+            .unwrap();
+        let v = file.check_integrity();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].span.map(|s| (s.start, s.end, s.start_offset)), Some((5, 5, 28)));
+    }
+
+    #[test]
+    fn constraints() {
+        assert_eq!(
+            messages("%rec: A\n%constraint: Age >= 18\n%constraint: Age <\n\nAge: 20\n\nAge: 3\n"),
+            vec![
+                "Invalid expression: unexpected end of expression (in %constraint Age <)",
+                "constraint Age >= 18 failed",
+            ]);
+    }
+}
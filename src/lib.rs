@@ -1,34 +1,173 @@
+// `failure_derive` generates its impls inside a constant
+#![allow(non_local_definitions)]
+
 #[macro_use] extern crate failure;
+extern crate regex;
+#[macro_use] extern crate serde;
+#[cfg(test)] #[macro_use] extern crate serde_derive;
+
+pub mod access;
+pub mod borrowed;
+pub mod cst;
+pub mod date;
+pub mod de;
+pub mod descriptor;
+pub mod diagnostic;
+pub mod edit;
+pub mod fex;
+pub mod group;
+pub mod insert;
+pub mod integrity;
+pub mod reader;
+pub mod select;
+pub mod ser;
+pub mod sex;
+pub mod sort;
+pub mod types;
+
+pub use access::TypedRecord;
+pub use borrowed::{BorrowedRecord, RecfileRef};
+pub use cst::Document;
+pub use de::from_record;
+pub use descriptor::RecordDescriptor;
+pub use diagnostic::{Diagnostic, Severity};
+pub use fex::FieldExpr;
+pub use integrity::Violation;
+pub use reader::{Entry, ParseOptions, RecordReader};
+pub use select::Selection;
+pub use ser::{to_recfile, to_record};
+pub use sex::Expr;
+pub use sort::SortKey;
+pub use types::FieldType;
+
+
+/// A `Span` is the range of (1-based, inclusive) lines in the source
+/// file that some parsed item came from, along with the byte offsets
+/// of its first character and of the end of its last line (not
+/// counting the line terminator).
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub start_offset: usize,
+    pub end_offset: usize,
+}
 
-pub mod contlines;
+impl Span {
+    fn extend_to(span: &mut Option<Span>, other: Span) {
+        match *span {
+            Some(ref mut s) => {
+                s.end = other.end;
+                s.end_offset = other.end_offset;
+            }
+            None => *span = Some(other),
+        }
+    }
+}
 
-use contlines::ContinuationLines;
+/// A `Position` is a single point in a source file, used to report
+/// where parse errors happened
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Position {
+    /// The name of the file, if the parser was given one
+    pub file: Option<String>,
+    /// The (1-based) line number
+    pub line: usize,
+    /// The (1-based) column, counted in characters
+    pub column: usize,
+    /// The byte offset from the start of the file
+    pub offset: usize,
+}
 
+impl Span {
+    /// An empty span at a single position
+    pub fn at(pos: &Position) -> Span {
+        Span {
+            start: pos.line,
+            end: pos.line,
+            start_offset: pos.offset,
+            end_offset: pos.offset,
+        }
+    }
+}
 
-struct ParsingContext {
-    current_record_type: Option<String>,
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
 }
 
 
 /// A `Record` is a single bundle of key-value pairs with a few pieces
 /// of optional metadata. This preserves the order of the values
-/// contained.
-#[derive(Eq, PartialEq, Debug)]
+/// contained. Two records are equal if their types and fields are
+/// equal, regardless of where they came from.
+#[derive(Eq, Debug, Clone, Default)]
 pub struct Record {
     pub rec_type: Option<String>,
     pub fields: Vec<(String, String)>,
+    /// The location of this record in its source file, if it was
+    /// parsed from one
+    pub span: Option<Span>,
+    /// The locations of the fields in the source file, in the same
+    /// order as `fields`. This may be shorter than `fields` (or
+    /// empty) when fields were added after parsing.
+    pub field_spans: Vec<Span>,
+    /// The places where values were split across lines with a
+    /// trailing backslash in the source, as pairs of the index of
+    /// the field and the offset into its value, so that writing the
+    /// record splits them the same way
+    pub splits: Vec<(usize, usize)>,
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Record) -> bool {
+        self.rec_type == other.rec_type && self.fields == other.fields
+    }
 }
 
 impl Record {
-    /// Write the serialized version of this `Record` to the provided `Write`r
+    /// Create a record with the given type and fields which wasn't
+    /// read from a file, and so has no spans or splits
+    pub fn new<I, K, V>(rec_type: Option<&str>, fields: I) -> Record
+        where I: IntoIterator<Item = (K, V)>, K: Into<String>, V: Into<String>
+    {
+        Record {
+            rec_type: rec_type.map(|t| t.to_owned()),
+            fields: fields.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+            span: None,
+            field_spans: vec![],
+            splits: vec![],
+        }
+    }
+
+    /// Write the serialized version of this `Record` to the provided
+    /// `Write`r, followed by the blank line that separates records
     pub fn write<W>(&self, w: &mut W) -> std::io::Result<()>
         where W: std::io::Write
     {
-        for &(ref name, ref value) in self.fields.iter() {
-            write!(w, "{}: {}\n", name, value)?;
+        self.write_fields(w)?;
+        writeln!(w)
+    }
+
+    /// Write the fields of this `Record` to the provided `Write`r
+    /// without a separating blank line afterwards. Values containing
+    /// newlines are written with `+` continuation lines.
+    pub fn write_fields<W>(&self, w: &mut W) -> std::io::Result<()>
+        where W: std::io::Write
+    {
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            let splits: Vec<usize> = self.splits.iter()
+                .filter(|&&(f, _)| f == i)
+                .map(|&(_, offset)| offset)
+                .collect();
+            w.write_all(edit::field_text_with_splits(name, value, &splits).as_bytes())?;
         }
 
-        write!(w, "\n")
+        Ok(())
     }
 
     /// Turn this `Record` into a serialized string representation
@@ -44,7 +183,7 @@ impl Record {
         self.fields.len()
     }
 
-    pub fn get_type<'a>(&'a self) -> Result<&'a str, RecError> {
+    pub fn get_type(&self) -> Result<&str, RecError> {
         match self.rec_type {
             Some(ref t) => Ok(t),
             None => Err(RecError::NoType),
@@ -55,31 +194,187 @@ impl Record {
     /// exists
     pub fn get<'a>(&'a self, name: &str) -> Result<&'a str, RecError> {
         self.fields.iter()
-            .find(|&&(ref p, _)| p == name)
-            .map(|&(_, ref q)| q.as_ref())
+            .find(|(p, _)| p == name)
+            .map(|(_, q)| q.as_ref())
             .ok_or(RecError::MissingField { name: name.to_owned() })
     }
+
+    /// Return the values of every field with the given name, in the
+    /// order they appear
+    pub fn get_all<'a>(&'a self, name: &str) -> Vec<&'a str> {
+        self.fields.iter()
+            .filter(|(p, _)| p == name)
+            .map(|(_, q)| q.as_ref())
+            .collect()
+    }
+
+    /// Change the value of the first field with the given name, or
+    /// add the field to the end of the record if it isn't present
+    pub fn set<V: Into<String>>(&mut self, name: &str, value: V) {
+        match self.fields.iter().position(|(p, _)| p == name) {
+            Some(pos) => {
+                self.fields[pos].1 = value.into();
+                self.renumber_splits(|i| if i == pos { None } else { Some(i) });
+            }
+            None => self.push(name, value),
+        }
+    }
+
+    /// Add a field to the end of the record, even if there is already
+    /// a field with the same name
+    pub fn push<V: Into<String>>(&mut self, name: &str, value: V) {
+        self.fields.push((name.to_owned(), value.into()));
+    }
+
+    /// Add a field at the given position, shifting the fields after
+    /// it along. This panics if the index is greater than the number
+    /// of fields.
+    pub fn insert_at<V: Into<String>>(&mut self, index: usize, name: &str, value: V) {
+        self.fields.insert(index, (name.to_owned(), value.into()));
+        // the fields after this one no longer line up with their spans
+        self.field_spans.truncate(index);
+        self.renumber_splits(|i| Some(if i >= index { i + 1 } else { i }));
+    }
+
+    /// Remove the first field with the given name, returning its
+    /// value if there was one
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let pos = self.fields.iter().position(|(p, _)| p == name)?;
+        if pos < self.field_spans.len() {
+            self.field_spans.remove(pos);
+        }
+        self.renumber_splits(|i| match i {
+            _ if i < pos => Some(i),
+            _ if i > pos => Some(i - 1),
+            _ => None,
+        });
+        Some(self.fields.remove(pos).1)
+    }
+
+    /// Remove every field with the given name, returning their values
+    pub fn remove_all(&mut self, name: &str) -> Vec<String> {
+        let mut removed = vec![];
+        self.retain(|k, v| if k == name {
+            removed.push(v.to_owned());
+            false
+        } else {
+            true
+        });
+        removed
+    }
+
+    /// Give every field with the name `from` the name `to` instead,
+    /// returning how many fields were renamed
+    pub fn rename(&mut self, from: &str, to: &str) -> usize {
+        let mut count = 0;
+        for field in self.fields.iter_mut().filter(|(p, _)| p == from) {
+            field.0 = to.to_owned();
+            count += 1;
+        }
+        count
+    }
+
+    /// Keep only the fields for which the predicate, given the name
+    /// and value of the field, returns `true`
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&str, &str) -> bool
+    {
+        let spans = std::mem::take(&mut self.field_spans);
+        let mut kept_spans = vec![];
+        // the new index of each field, if it was kept
+        let mut indexes = vec![];
+        let mut kept = 0;
+        self.fields.retain(|(k, v)| {
+            let keep = f(k, v);
+            if keep {
+                kept_spans.extend(spans.get(indexes.len()));
+                indexes.push(Some(kept));
+                kept += 1;
+            } else {
+                indexes.push(None);
+            }
+            keep
+        });
+        self.field_spans = kept_spans;
+        self.renumber_splits(|i| indexes.get(i).cloned().flatten());
+    }
+
+    /// Update the field indexes of `splits` after fields have moved,
+    /// dropping the splits of fields which are gone
+    fn renumber_splits<F>(&mut self, f: F)
+        where F: Fn(usize) -> Option<usize>
+    {
+        self.splits = self.splits.iter()
+            .filter_map(|&(i, offset)| f(i).map(|i| (i, offset)))
+            .collect();
+    }
+
+    /// Return the location of the first field with the given name in
+    /// the source file, if it was parsed from one
+    pub fn field_span(&self, name: &str) -> Option<Span> {
+        let pos = self.fields.iter().position(|(p, _)| p == name)?;
+        self.field_spans.get(pos).cloned()
+    }
 }
 
 
-/// A `Recfile` is a sequence of `Record`.
+/// A `Recfile` is a sequence of `Record`, along with the
+/// `RecordDescriptor`s that describe the types of those records.
 #[derive(Eq, PartialEq, Debug)]
 pub struct Recfile {
     pub records: Vec<Record>,
+    pub descriptors: Vec<RecordDescriptor>,
 }
 
 impl Recfile {
-    /// Serialize this `Recfile` to the provided `Write`r
+    /// Serialize this `Recfile` to the provided `Write`r. Records
+    /// without a descriptor come first, and the records of each type
+    /// follow their descriptor. This writes every record afresh; use
+    /// a `Document` to change a file while keeping its comments and
+    /// formatting.
     pub fn write<W>(&self, w: &mut W) -> std::io::Result<()>
         where W: std::io::Write
     {
         for r in self.records.iter() {
-            r.write(w)?;
+            let described = match r.rec_type {
+                Some(ref t) => self.descriptor(t).is_some(),
+                None => false,
+            };
+            if !described {
+                r.write(w)?;
+            }
+        }
+
+        let mut seen: Vec<&str> = vec![];
+        for d in self.descriptors.iter() {
+            if seen.contains(&d.rec_type.as_str()) {
+                continue;
+            }
+            seen.push(&d.rec_type);
+            d.write(w)?;
+            for r in self.iter_by_type(&d.rec_type) {
+                r.write(w)?;
+            }
         }
 
         Ok(())
     }
 
+    /// Return the descriptor for the named record type, if there is
+    /// one
+    pub fn descriptor(&self, type_name: &str) -> Option<&RecordDescriptor> {
+        self.descriptors.iter().find(|d| d.rec_type == type_name)
+    }
+
+    /// Return the descriptor which applies to the provided record,
+    /// if there is one
+    pub fn descriptor_for(&self, rec: &Record) -> Option<&RecordDescriptor> {
+        match rec.rec_type {
+            Some(ref t) => self.descriptor(t),
+            None => None,
+        }
+    }
+
     /// Turn this `Recfile` into a serialized string representation
     pub fn to_string(&self) -> std::io::Result<String> {
         let mut s = std::io::Cursor::new(Vec::new());
@@ -88,13 +383,14 @@ impl Recfile {
         Ok(String::from_utf8(s.into_inner()).unwrap())
     }
 
-    /// Modify this Recfile in-place by only keeping the records of a
-    /// particular type
+    /// Modify this Recfile in-place by only keeping the records (and
+    /// the descriptor) of a particular type
     pub fn filter_by_type(&mut self, type_name: &str) {
         self.records.retain(|r| match r.rec_type {
             Some(ref t) => t == type_name,
             None => false,
         });
+        self.descriptors.retain(|d| d.rec_type == type_name);
     }
 
     /// Iterate over a subset of the records in this recfile
@@ -106,7 +402,7 @@ impl Recfile {
     }
 
     /// Iterate over _all_ the records in this recfile
-    pub fn iter<'a>(&'a self) -> std::slice::Iter<'a, Record> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Record> {
         self.records.iter()
     }
 }
@@ -120,31 +416,31 @@ impl<'a> Iterator for RecIterator<'a> {
     type Item = &'a Record;
 
     fn next(&mut self) -> Option<&'a Record> {
-        while let Some(r) = self.rec.next() {
-            match r.rec_type {
-                Some(ref n) if n == self.typ => return Some(r),
-                _ => (),
-            }
-        }
-        return None;
+        let typ = self.typ;
+        self.rec.by_ref().find(|r| match r.rec_type {
+            Some(ref n) => n == typ,
+            None => false,
+        })
     }
 }
 
-#[derive(Debug, Fail)]
+#[derive(Debug, PartialEq, Clone, Fail)]
 pub enum RecError {
     #[fail(display = "Error parsing records: {}", message)]
     GenericError {
         message: String,
     },
 
-    #[fail(display = "Found cont line in nonsensical place: {}", ln)]
+    #[fail(display = "{}: Found cont line in nonsensical place: {}", pos, ln)]
     BadContLine {
         ln: String,
+        pos: Position,
     },
 
-    #[fail(display = "Invalid line: {}", ln)]
+    #[fail(display = "{}: Invalid line: {}", pos, ln)]
     InvalidLine {
         ln: String,
+        pos: Position,
     },
 
     #[fail(display = "Missing key: {}", name)]
@@ -154,85 +450,176 @@ pub enum RecError {
 
     #[fail(display = "Missing type for record")]
     NoType,
+
+    #[fail(display = "{}: Invalid record descriptor: {}", pos, message)]
+    InvalidDescriptor {
+        message: String,
+        pos: Position,
+    },
+
+    #[fail(display = "Value of field {} is not a valid {}: {}", name, typ, value)]
+    TypeMismatch {
+        name: String,
+        value: String,
+        typ: String,
+    },
+
+    #[fail(display = "Value of field {} could not be read as {}: {}", name, expected, value)]
+    InvalidValue {
+        name: String,
+        value: String,
+        expected: String,
+    },
+
+    #[fail(display = "Error converting record: {}", message)]
+    ConversionError {
+        message: String,
+    },
+
+    #[fail(display = "Invalid expression: {}", message)]
+    InvalidExpression {
+        message: String,
+    },
+
+    #[fail(display = "{}: Error reading input: {}", pos, message)]
+    Io {
+        kind: std::io::ErrorKind,
+        message: String,
+        pos: Position,
+    },
+
+    #[fail(display = "{}: Invalid UTF-8 byte 0x{:02x}", pos, byte)]
+    InvalidEncoding {
+        byte: u8,
+        pos: Position,
+    },
+
+    #[fail(display = "{}: Invalid field name: {}", pos, name)]
+    InvalidFieldName {
+        name: String,
+        pos: Position,
+    },
+
+    #[fail(display = "{}: More than {} {}", pos, limit, what)]
+    LimitExceeded {
+        what: String,
+        limit: usize,
+        pos: Position,
+    },
+}
+
+impl RecError {
+    /// Where in the input this error happened, if it came from
+    /// reading a particular line
+    pub fn position(&self) -> Option<&Position> {
+        match self {
+            RecError::BadContLine { pos, .. }
+            | RecError::InvalidLine { pos, .. }
+            | RecError::Io { pos, .. }
+            | RecError::InvalidEncoding { pos, .. }
+            | RecError::InvalidDescriptor { pos, .. }
+            | RecError::InvalidFieldName { pos, .. }
+            | RecError::LimitExceeded { pos, .. } => Some(pos),
+            _ => None,
+        }
+    }
+
+    /// Place an `InvalidDescriptor` error at the start of the
+    /// descriptor it came from, which isn't known where the problem
+    /// is found
+    fn in_descriptor(self, span: Option<Span>) -> RecError {
+        match self {
+            RecError::InvalidDescriptor { message, .. } => {
+                let span = span.unwrap_or_default();
+                RecError::InvalidDescriptor {
+                    message,
+                    pos: Position {
+                        file: None,
+                        line: span.start,
+                        column: 1,
+                        offset: span.start_offset,
+                    },
+                }
+            }
+            e => e,
+        }
+    }
 }
 
 
 impl Recfile {
+    /// Parse a whole recfile, collecting all of its records and
+    /// descriptors. Use a `RecordReader` to read the records one at a
+    /// time instead, or to decode files which are not UTF-8.
     pub fn parse<I>(i: I) -> Result<Recfile, RecError>
         where I: std::io::BufRead
     {
-        let mut iter = ContinuationLines::new(i.lines());
-        let mut current = Record {
-            fields: vec![],
-            rec_type: None,
-        };
-        let mut buf = vec![];
-        let mut ctx = ParsingContext {
-            current_record_type: None,
-        };
+        Recfile::from_reader(RecordReader::new(i))
+    }
 
-        while let Some(Ok(ln)) = iter.next() {
-            let ln = ln.trim_left_matches(' ');
-
-            if ln.starts_with('#') {
-                // skip comment lines
-            } else if ln.is_empty() {
-                if !current.fields.is_empty() {
-                    buf.push(current);
-                    current = Record {
-                        rec_type: ctx.current_record_type.clone(),
-                        fields: vec![],
-                    };
-                }
-            } else if ln.starts_with('+') {
-                if let Some(val) = current.fields.last_mut() {
-                    val.1.push_str("\n");
-                    val.1.push_str(
-                        if ln[1..].starts_with(' ') {
-                            &ln[2..]
-                        } else {
-                            &ln[1..]
-                        });
-                } else {
-                    return Err(RecError::BadContLine{ ln: ln.to_owned() });
-                }
-            } else if let Some(pos) = ln.find(':') {
-                let (key, val) = ln.split_at(pos);
-                current.fields.push((
-                    key.to_owned(),
-                    val[1..].trim_left().to_owned()));
-                if key == "%rec" {
-                    ctx.current_record_type = Some(val[1..].trim_left().to_owned());
-                    current.rec_type = None;
-                }
-            } else {
-                return Err(RecError::InvalidLine { ln: ln.to_owned() });
-            }
-        }
+    /// Parse a whole recfile according to some `ParseOptions`,
+    /// returning it along with any warnings about the input
+    pub fn parse_with<I>(i: I, options: &ParseOptions) -> Result<(Recfile, Vec<RecError>), RecError>
+        where I: std::io::BufRead
+    {
+        let mut reader = RecordReader::new(i).with_options(options);
+        let file = Recfile::read_entries(&mut reader)?;
+        Ok((file, reader.warnings().to_vec()))
+    }
 
-        if !current.fields.is_empty() {
-            buf.push(current);
-        }
+    /// Parse as much of a recfile as possible, skipping any records
+    /// which contain errors. This returns the records which could be
+    /// read along with diagnostics for all of the problems found.
+    pub fn parse_recovering<I>(i: I) -> (Recfile, Vec<Diagnostic>)
+        where I: std::io::BufRead
+    {
+        Recfile::from_reader_recovering(RecordReader::new(i))
+    }
+
+    /// Collect as much of a recfile from a reader as possible, as
+    /// `parse_recovering` does, e.g. from one which has been given a
+    /// file name to use in diagnostics
+    pub fn from_reader_recovering<I>(reader: RecordReader<I>) -> (Recfile, Vec<Diagnostic>)
+        where I: std::io::BufRead
+    {
+        let mut reader = reader.recovering();
+        // a recovering reader reports errors as diagnostics instead
+        let file = Recfile::read_entries(&mut reader)
+            .unwrap_or_else(|_| Recfile { records: vec![], descriptors: vec![] });
+        (file, reader.diagnostics())
+    }
 
-        Ok(Recfile { records: buf })
+    /// Collect all of the records and descriptors from a reader, e.g.
+    /// one which has been given a file name to use in errors
+    pub fn from_reader<I>(mut reader: RecordReader<I>) -> Result<Recfile, RecError>
+        where I: std::io::BufRead
+    {
+        Recfile::read_entries(&mut reader)
     }
 
+    fn read_entries<I>(reader: &mut RecordReader<I>) -> Result<Recfile, RecError>
+        where I: std::io::BufRead
+    {
+        let mut records = vec![];
+        let mut descriptors = vec![];
+        while let Some(entry) = reader.next_entry() {
+            match entry? {
+                Entry::Record(r) => records.push(r),
+                Entry::Descriptor(d) => descriptors.push(*d),
+            }
+        }
+        Ok(Recfile { records, descriptors })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use ::{Recfile,Record};
+    use ::{Recfile,Record,RecordDescriptor,Span};
 
     fn test_parse(input: &[u8], expected: Vec<Vec<(&str, &str)>>) {
         let file = Recfile {
-            records: expected.iter().map( |v| {
-                Record {
-                    rec_type: None,
-                    fields: v.iter().map( |&(k, v)| {
-                        (k.to_owned(), v.to_owned())
-                    }).collect(),
-                }
-            }).collect(),
+            records: expected.into_iter().map(|v| Record::new(None, v)).collect(),
+            descriptors: vec![],
         };
         assert_eq!(Recfile::parse(input), Ok(file));
     }
@@ -293,4 +680,163 @@ mod tests {
         );
     }
 
+    #[test]
+    fn descriptors_are_separate() {
+        let file = Recfile::parse(&b"%rec: Book\n%mandatory: Title\n\nTitle: Mio Cid\n"[..])
+            .unwrap();
+        assert_eq!(file.records.len(), 1);
+        assert_eq!(file.records[0].rec_type, Some("Book".to_owned()));
+        assert_eq!(file.descriptors.len(), 1);
+        assert_eq!(file.descriptors[0].mandatory, vec!["Title"]);
+    }
+
+    #[test]
+    fn descriptor_lookup_by_type() {
+        let file = Recfile::parse(
+            &b"Id: 0\n\n%rec: A\n\nId: 1\n\n%rec: B\n%key: Id\n\nId: 2\n"[..],
+        ).unwrap();
+        assert_eq!(file.records[0].rec_type, None);
+        assert_eq!(file.descriptor_for(&file.records[0]), None);
+        assert_eq!(file.descriptor("B").and_then(|d| d.key.clone()), Some("Id".to_owned()));
+        let b: Option<&RecordDescriptor> = file.descriptor_for(&file.records[2]);
+        assert_eq!(b.map(|d| d.rec_type.as_str()), Some("B"));
+        assert!(file.descriptor("C").is_none());
+    }
+
+    #[test]
+    fn write_places_records_after_descriptors() {
+        let input = "Id: 0\n\n%rec: A\n\nId: 1\n\n%rec: B\n\nId: 2\n\n";
+        let file = Recfile::parse(input.as_bytes()).unwrap();
+        assert_eq!(file.to_string().unwrap(), input);
+    }
+
+    #[test]
+    fn write_gives_each_type_one_descriptor() {
+        let input = "%rec: A\n\nId: 1\n\n%rec: B\n\nId: 2\n\n%rec: A\n\nId: 3\n";
+        let file = Recfile::parse(input.as_bytes()).unwrap();
+        assert_eq!(file.to_string().unwrap(), "%rec: A\n\nId: 1\n\nId: 3\n\n%rec: B\n\nId: 2\n\n");
+    }
+
+    #[test]
+    fn record_spans() {
+        let file = Recfile::parse(
+            &b"# a comment\n%rec: A\n\nId: 1\nNote: one\\\n two\n+ three\n\n\nId: 2\n"[..],
+        ).unwrap();
+        let span = |start, end, start_offset, end_offset| {
+            Some(Span { start, end, start_offset, end_offset })
+        };
+        assert_eq!(file.descriptors[0].span, span(2, 2, 12, 19));
+        assert_eq!(file.records[0].span, span(4, 7, 21, 50));
+        assert_eq!(file.records[0].field_span("Note"), span(5, 7, 27, 50));
+        assert_eq!(file.records[1].span, span(10, 10, 53, 58));
+        assert_eq!(file.records[1].field_span("Id"), span(10, 10, 53, 58));
+
+        let mut rec = file.records[0].clone();
+        rec.remove("Id");
+        assert_eq!(rec.field_span("Note"), span(5, 7, 27, 50));
+        rec.insert_at(0, "Id", "2");
+        assert_eq!(rec.field_span("Note"), None);
+    }
+
+    #[test]
+    fn record_mutation() {
+        let mut rec = Recfile::parse(
+            &b"Title: Mio Cid\nAuthor: a\nAuthor: b\n"[..],
+        ).unwrap().records.remove(0);
+        assert_eq!(rec.get_all("Author"), vec!["a", "b"]);
+        assert!(rec.get_all("Year").is_empty());
+
+        rec.set("Title", "El Cantar");
+        rec.set("Year", "1200");
+        rec.push("Author", "c");
+        rec.insert_at(0, "Id", "1");
+        assert_eq!(rec.get("Title").unwrap(), "El Cantar");
+        assert_eq!(rec.fields[0].0, "Id");
+        assert_eq!(rec.fields[4].0, "Year");
+        assert_eq!(rec.get_all("Author"), vec!["a", "b", "c"]);
+
+        assert_eq!(rec.remove("Author"), Some("a".to_owned()));
+        assert_eq!(rec.remove("Missing"), None);
+        assert_eq!(rec.rename("Author", "Writer"), 2);
+        assert_eq!(rec.remove_all("Writer"), vec!["b", "c"]);
+        rec.retain(|k, _| k != "Id");
+        assert_eq!(rec.fields, vec![
+            ("Title".to_owned(), "El Cantar".to_owned()),
+            ("Year".to_owned(), "1200".to_owned()),
+        ]);
+    }
+
+    /// A small deterministic generator for the round-trip tests
+    struct Gen(u64);
+
+    impl Gen {
+        fn below(&mut self, n: usize) -> usize {
+            // xorshift64
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick(&mut self, chars: &[char], len: usize) -> String {
+            (0..len).map(|_| chars[self.below(chars.len())]).collect()
+        }
+
+        fn record(&mut self) -> Record {
+            let name_start = ['a', 'Z', '%'];
+            let name_rest = ['a', 'z', 'Q', '0', '9', '_'];
+            let value_chars = [
+                'a', 'b', 'Z', '0', ' ', ' ', '\t', '\n', '\n', ':', '#', '+', '\\', '%', 'é', '☃',
+            ];
+            let mut rec = Record::default();
+            for _ in 0..self.below(6) + 1 {
+                let mut name = self.pick(&name_start, 1);
+                let len = self.below(5);
+                name.push_str(&self.pick(&name_rest, len));
+                let len = self.below(20);
+                let value = self.pick(&value_chars, len);
+                // split the value at a few places across lines
+                for _ in 0..self.below(3) {
+                    let offset = self.below(value.len() + 1);
+                    rec.splits.push((rec.fields.len(), offset));
+                }
+                rec.push(&name, value);
+            }
+            rec
+        }
+    }
+
+    #[test]
+    fn write_then_parse_round_trips() {
+        let mut gen = Gen(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let rec = gen.record();
+            let text = rec.to_string().unwrap();
+            let parsed = Recfile::parse(text.as_bytes()).unwrap();
+            assert_eq!(parsed.records, vec![rec], "while parsing {:?}", text);
+            // the splits which could be written are kept
+            assert_eq!(parsed.records[0].to_string().unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn backslash_continuation() {
+        let src = "# C:\\Windows\\\nPath: C:\\Windows\\\\\nNote: one \\\ntwo\\\n\n+ three\n";
+        let file = Recfile::parse(src.as_bytes()).unwrap();
+        let rec = &file.records[0];
+        assert_eq!(rec.get("Path").unwrap(), "C:\\Windows\\");
+        assert_eq!(rec.get("Note").unwrap(), "one two\nthree");
+        assert_eq!(rec.splits, vec![(1, 4), (1, 7)]);
+        assert_eq!(rec.field_span("Note").map(|s| (s.start, s.end)), Some((3, 6)));
+        assert_eq!(rec.to_string().unwrap(), format!("{}\n", &src[14..]));
+    }
+
+    #[test]
+    fn writing_multi_line_values() {
+        let mut rec = Record::default();
+        rec.push("Note", "  indented\n\n  more\n");
+        assert_eq!(rec.to_string().unwrap(), "Note:   indented\n+\n+   more\n+\n\n");
+        let parsed = Recfile::parse(rec.to_string().unwrap().as_bytes()).unwrap();
+        assert_eq!(parsed.records[0].get("Note").unwrap(), "  indented\n\n  more\n");
+    }
 }
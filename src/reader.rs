@@ -0,0 +1,979 @@
+//! Reading records one at a time. `Recfile::parse` collects every
+//! record of a file at once, but a `RecordReader` only holds on to
+//! the record it is currently reading (and the descriptor in effect
+//! for it), so large files can be filtered in constant memory.
+
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+use std::ops::Range;
+
+use borrowed::BorrowedRecord;
+use diagnostic::{Diagnostic, Severity};
+use {Position, RecError, Record, RecordDescriptor, Span};
+
+/// Something read from a recfile: either a plain record, or a
+/// descriptor which applies to the records after it
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Entry {
+    Record(Record),
+    Descriptor(Box<RecordDescriptor>),
+}
+
+/// Split a field line into its name and value. As in GNU recutils,
+/// a single blank after the colon separates the two, and any further
+/// whitespace is part of the value.
+pub fn split_field(line: &str) -> Option<(&str, &str)> {
+    let (name, rest) = line.split_once(':')?;
+    let value = rest.strip_prefix(' ')
+        .or_else(|| rest.strip_prefix('\t'))
+        .unwrap_or(rest);
+    Some((name, value))
+}
+
+/// Whether a line of a field's value ends in a backslash which joins
+/// it to the next line. A doubled backslash at the end of a line is a
+/// literal backslash instead.
+pub fn ends_with_split(line: &str) -> bool {
+    line.ends_with('\\') && !line.ends_with("\\\\")
+}
+
+/// Add a line of a field's value to the value read so far, removing
+/// the backslash which escapes a trailing backslash or splits the
+/// value across lines. Returns whether the value continues on the
+/// next line.
+pub fn push_value_line(value: &mut String, line: &str) -> bool {
+    if ends_with_split(line) {
+        value.push_str(&line[..line.len() - 1]);
+        true
+    } else {
+        value.push_str(line.strip_suffix('\\').unwrap_or(line));
+        false
+    }
+}
+
+/// Options controlling how strictly recfiles are parsed, and how much
+/// input will be accepted
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Only accept input which follows the recfile format exactly:
+    /// field names must match `[a-zA-Z%][a-zA-Z0-9_]*`, and lines may
+    /// not start with whitespace
+    pub strict: bool,
+    /// Skip invalid lines, reporting them as warnings, instead of
+    /// failing at the first one
+    pub lenient: bool,
+    /// Decode lines which are not valid UTF-8 as Latin-1, reporting
+    /// them as warnings
+    pub lossy: bool,
+    /// The longest line accepted, in bytes
+    pub max_line_length: Option<usize>,
+    /// The most fields accepted in a single record
+    pub max_fields: Option<usize>,
+    /// The most records accepted in a file, not counting descriptors
+    pub max_records: Option<usize>,
+}
+
+/// Whether a field name is valid according to the recfile format,
+/// i.e. whether it matches `[a-zA-Z%][a-zA-Z0-9_]*`
+pub fn valid_field_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '%' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Where the lines of a record come from. `RecordReader` reads owned
+/// lines from a `BufRead`, while `StrReader` borrows them from a string.
+trait LineSource<'a> {
+    /// Read the next line without its terminator
+    fn next_line(&mut self) -> Result<Option<Cow<'a, str>>, RecError>;
+    /// The name of the file being read, for error messages
+    fn file(&self) -> Option<&str>;
+    /// The number of lines read so far
+    fn line(&self) -> usize;
+    /// The offset of the start of the next line
+    fn offset(&self) -> usize;
+    /// The offset of the end of the last line read, not counting its
+    /// terminator
+    fn last_end(&self) -> usize;
+    /// The length of the byte order mark, if the input started with one
+    fn bom(&self) -> usize;
+    /// Report a problem which didn't stop the input being read
+    fn warn(&mut self, problem: RecError);
+
+    fn position_at(&self, line: usize, offset: usize, column: usize) -> Position {
+        Position { file: self.file().map(|f| f.to_owned()), line, column, offset }
+    }
+}
+
+/// An iterator over the lines of a file, like `BufRead::lines`, which
+/// also keeps track of the byte offsets of the lines it returns. A
+/// UTF-8 byte order mark at the start of the file is skipped, and
+/// lines may end in either `\n` or `\r\n`.
+struct RawLines<R: BufRead> {
+    input: R,
+    /// The name of the file being read, for error messages
+    file: Option<String>,
+    /// The number of lines read so far
+    line: usize,
+    /// The offset of the start of the next line
+    offset: usize,
+    /// The offset of the end of the last line returned, not counting
+    /// its terminator
+    last_end: usize,
+    /// The length of the byte order mark, if the file started with one
+    bom: usize,
+    /// Whether to decode invalid UTF-8 as Latin-1 instead of failing
+    lossy: bool,
+    /// The longest line to read, in bytes
+    max_line_length: Option<usize>,
+    /// The encoding problems that were worked around in lossy mode
+    warnings: Vec<RecError>,
+    /// The error behind the last `Err` returned, with its position
+    error: Option<RecError>,
+}
+
+impl<R: BufRead> RawLines<R> {
+    /// Decode a line, either failing at the first invalid byte or (in
+    /// lossy mode) falling back to Latin-1 for the whole line
+    fn decode(&mut self, start: usize, buf: Vec<u8>) -> io::Result<String> {
+        let err = match String::from_utf8(buf) {
+            Ok(s) => return Ok(s),
+            Err(err) => err,
+        };
+        let valid = err.utf8_error().valid_up_to();
+        let bytes = err.as_bytes();
+        let column = std::str::from_utf8(&bytes[..valid]).map_or(0, |s| s.chars().count()) + 1;
+        let problem = RecError::InvalidEncoding {
+            byte: bytes[valid],
+            pos: self.position_at(self.line, start + valid, column),
+        };
+        if self.lossy {
+            self.warnings.push(problem);
+            Ok(err.into_bytes().into_iter().map(char::from).collect())
+        } else {
+            let e = io::Error::new(io::ErrorKind::InvalidData, problem.to_string());
+            self.error = Some(problem);
+            Err(e)
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RawLines<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut buf = vec![];
+        let read = match self.max_line_length {
+            // leave room for the BOM and terminator, but don't read
+            // any more of an overlong line than we have to
+            Some(max) => <&mut R as io::Read>::take(&mut self.input, max as u64 + 5).read_until(b'\n', &mut buf),
+            None => self.input.read_until(b'\n', &mut buf),
+        };
+        match read {
+            Ok(0) => None,
+            Ok(n) => {
+                let mut start = self.offset;
+                self.offset += n;
+                self.line += 1;
+                if self.line == 1 && buf.starts_with(b"\xef\xbb\xbf") {
+                    buf.drain(..3);
+                    self.bom = 3;
+                    start += 3;
+                }
+                if buf.ends_with(b"\n") {
+                    buf.pop();
+                    if buf.ends_with(b"\r") {
+                        buf.pop();
+                    }
+                }
+                if let Some(max) = self.max_line_length.filter(|&max| buf.len() > max) {
+                    let problem = RecError::LimitExceeded {
+                        what: "bytes in a line".to_owned(),
+                        limit: max,
+                        pos: self.position_at(self.line, start + max, max + 1),
+                    };
+                    let e = io::Error::new(io::ErrorKind::InvalidData, problem.to_string());
+                    self.error = Some(problem);
+                    return Some(Err(e));
+                }
+                self.last_end = start + buf.len();
+                Some(self.decode(start, buf))
+            }
+            Err(e) => {
+                self.error = Some(RecError::Io {
+                    kind: e.kind(),
+                    message: e.to_string(),
+                    pos: self.position_at(self.line + 1, self.offset, 1),
+                });
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a, R: BufRead> LineSource<'a> for RawLines<R> {
+    fn next_line(&mut self) -> Result<Option<Cow<'a, str>>, RecError> {
+        match self.next() {
+            Some(Ok(ln)) => Ok(Some(Cow::Owned(ln))),
+            Some(Err(e)) => Err(self.error.take().unwrap_or_else(|| RecError::Io {
+                kind: e.kind(),
+                message: e.to_string(),
+                pos: self.position_at(self.line, self.offset, 1),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    fn line(&self) -> usize {
+        self.line
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn last_end(&self) -> usize {
+        self.last_end
+    }
+
+    fn bom(&self) -> usize {
+        self.bom
+    }
+
+    fn warn(&mut self, problem: RecError) {
+        self.warnings.push(problem);
+    }
+}
+
+/// The lines of a string, which are borrowed from it instead of being
+/// copied. Like `RawLines`, this skips a byte order mark and accepts
+/// `\r\n` line endings.
+struct StrLines<'a> {
+    /// The part of the string which hasn't been read yet
+    rest: &'a str,
+    line: usize,
+    offset: usize,
+    last_end: usize,
+    bom: usize,
+    max_line_length: Option<usize>,
+    warnings: Vec<RecError>,
+}
+
+impl<'a> LineSource<'a> for StrLines<'a> {
+    fn next_line(&mut self) -> Result<Option<Cow<'a, str>>, RecError> {
+        if self.rest.is_empty() {
+            return Ok(None);
+        }
+        let len = self.rest.find('\n').map_or(self.rest.len(), |i| i + 1);
+        let (ln, rest) = self.rest.split_at(len);
+        let mut start = self.offset;
+        self.rest = rest;
+        self.offset += len;
+        self.line += 1;
+        let mut ln = ln.strip_suffix('\n').unwrap_or(ln);
+        ln = ln.strip_suffix('\r').unwrap_or(ln);
+        if self.line == 1 {
+            if let Some(stripped) = ln.strip_prefix('\u{feff}') {
+                ln = stripped;
+                self.bom = 3;
+                start += 3;
+            }
+        }
+        if let Some(max) = self.max_line_length.filter(|&max| ln.len() > max) {
+            return Err(RecError::LimitExceeded {
+                what: "bytes in a line".to_owned(),
+                limit: max,
+                pos: self.position_at(self.line, start + max, max + 1),
+            });
+        }
+        self.last_end = start + ln.len();
+        Ok(Some(Cow::Borrowed(ln)))
+    }
+
+    fn file(&self) -> Option<&str> {
+        None
+    }
+
+    fn line(&self) -> usize {
+        self.line
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn last_end(&self) -> usize {
+        self.last_end
+    }
+
+    fn bom(&self) -> usize {
+        self.bom
+    }
+
+    fn warn(&mut self, problem: RecError) {
+        self.warnings.push(problem);
+    }
+}
+
+/// Take part of a line, borrowing it if the line is borrowed
+fn part<'a>(line: &Cow<'a, str>, range: Range<usize>) -> Cow<'a, str> {
+    match *line {
+        Cow::Borrowed(s) => Cow::Borrowed(&s[range]),
+        Cow::Owned(ref s) => Cow::Owned(s[range].to_owned()),
+    }
+}
+
+/// Add a line of text to the value of the last field of a record,
+/// along with the lines after it if it ends in a backslash. The value
+/// is only copied if it has to be joined to other lines.
+fn read_value<'a, L: LineSource<'a>>(lines: &mut L, rec: &mut BorrowedRecord<'a>, line: Cow<'a, str>,
+                                     span: &mut Span) -> Result<(), RecError> {
+    let index = rec.fields.len() - 1;
+    let value = &mut rec.fields[index].1;
+    if value.is_empty() && !ends_with_split(&line) {
+        // an escaped backslash at the end is just a shorter slice
+        let len = line.strip_suffix('\\').unwrap_or(&line).len();
+        *value = match line {
+            Cow::Borrowed(s) => Cow::Borrowed(&s[..len]),
+            Cow::Owned(mut s) => {
+                s.truncate(len);
+                Cow::Owned(s)
+            }
+        };
+        return Ok(());
+    }
+    let mut line = line;
+    while push_value_line(value.to_mut(), &line) {
+        line = match lines.next_line()? {
+            Some(ln) => ln,
+            None => break,
+        };
+        rec.splits.push((index, value.len()));
+        span.end = lines.line();
+        span.end_offset = lines.last_end();
+    }
+    Ok(())
+}
+
+/// Read lines up to the end of the next non-empty record. This is
+/// shared by `RecordReader` and `StrReader`, which differ only in
+/// whether their lines are copied. The record has no type, as that
+/// depends on the descriptors which have been read.
+fn parse_record<'a, L: LineSource<'a>>(lines: &mut L, options: &ParseOptions)
+                                       -> Result<Option<BorrowedRecord<'a>>, RecError> {
+    let mut current = BorrowedRecord {
+        rec_type: None,
+        fields: vec![],
+        span: None,
+        field_spans: vec![],
+        splits: vec![],
+    };
+
+    loop {
+        let first_line = lines.line() + 1;
+        let mut start_offset = lines.offset();
+        let ln = match lines.next_line()? {
+            Some(ln) => ln,
+            None => break,
+        };
+        if first_line == 1 {
+            // skip the byte order mark, if there was one
+            start_offset += lines.bom();
+        }
+        let mut span = Span {
+            start: first_line,
+            end: lines.line(),
+            start_offset,
+            end_offset: lines.last_end(),
+        };
+        let indent = ln.len() - ln.trim_start_matches(' ').len();
+        let text = &ln[indent..];
+        let pos = lines.position_at(first_line, start_offset + indent, indent + 1);
+
+        let problem = if options.strict && indent > 0 {
+            Some(RecError::InvalidLine { ln: text.to_owned(), pos })
+        } else if text.starts_with('#') {
+            // skip comment lines
+            None
+        } else if text.is_empty() {
+            if !current.fields.is_empty() {
+                return Ok(Some(current));
+            }
+            None
+        } else if let Some(rest) = text.strip_prefix('+') {
+            if !current.fields.is_empty() {
+                let index = current.fields.len() - 1;
+                current.fields[index].1.to_mut().push('\n');
+                let rest = rest.strip_prefix(' ').unwrap_or(rest);
+                let value = part(&ln, ln.len() - rest.len()..ln.len());
+                read_value(lines, &mut current, value, &mut span)?;
+                Span::extend_to(&mut current.span, span);
+                if let Some(field) = current.field_spans.last_mut() {
+                    field.end = span.end;
+                    field.end_offset = span.end_offset;
+                }
+                None
+            } else {
+                Some(RecError::BadContLine { ln: text.to_owned(), pos })
+            }
+        } else if let Some((key, val)) = split_field(text) {
+            if options.strict && !valid_field_name(key) {
+                Some(RecError::InvalidFieldName { name: key.to_owned(), pos })
+            } else if options.max_fields.is_some_and(|max| current.fields.len() >= max) {
+                // too many fields is never worth recovering from
+                return Err(RecError::LimitExceeded {
+                    what: "fields in a record".to_owned(),
+                    limit: options.max_fields.unwrap_or_default(),
+                    pos,
+                });
+            } else {
+                let name = part(&ln, indent..indent + key.len());
+                let value = part(&ln, ln.len() - val.len()..ln.len());
+                current.fields.push((name, Cow::Borrowed("")));
+                read_value(lines, &mut current, value, &mut span)?;
+                current.field_spans.push(span);
+                Span::extend_to(&mut current.span, span);
+                None
+            }
+        } else {
+            Some(RecError::InvalidLine { ln: text.to_owned(), pos })
+        };
+
+        if let Some(problem) = problem {
+            if !options.lenient {
+                return Err(problem);
+            }
+            lines.warn(problem);
+        }
+    }
+
+    if current.fields.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(current))
+    }
+}
+
+/// An iterator over the records of a recfile which parses them as
+/// it goes. Descriptors are not yielded by the iterator itself, but
+/// the one in effect for the most recent record is available from
+/// `RecordReader::descriptor`.
+pub struct RecordReader<R: BufRead> {
+    lines: RawLines<R>,
+    descriptor: Option<RecordDescriptor>,
+    options: ParseOptions,
+    /// The number of records read so far
+    records: usize,
+    /// Whether to report errors as diagnostics and carry on reading
+    recovering: bool,
+    /// The errors recovered from so far
+    errors: Vec<Diagnostic>,
+    /// Whether the reader is partway through a record
+    in_record: bool,
+    /// The location of the last record read
+    last_span: Option<Span>,
+    /// Set after an error, so that nothing more is read
+    done: bool,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(input: R) -> RecordReader<R> {
+        let lines = RawLines {
+            input,
+            file: None,
+            line: 0,
+            offset: 0,
+            last_end: 0,
+            bom: 0,
+            lossy: false,
+            max_line_length: None,
+            warnings: vec![],
+            error: None,
+        };
+        RecordReader {
+            lines,
+            descriptor: None,
+            options: ParseOptions::default(),
+            records: 0,
+            recovering: false,
+            errors: vec![],
+            in_record: false,
+            last_span: None,
+            done: false,
+        }
+    }
+
+    /// Parse the input according to some `ParseOptions`
+    pub fn with_options(mut self, options: &ParseOptions) -> RecordReader<R> {
+        let raw = &mut self.lines;
+        raw.lossy = options.lossy;
+        raw.max_line_length = options.max_line_length;
+        self.options = options.clone();
+        self
+    }
+
+    /// Set the name of the file being read, which is included in the
+    /// positions of any errors
+    pub fn with_file_name<S: Into<String>>(mut self, name: S) -> RecordReader<R> {
+        self.lines.file = Some(name.into());
+        self
+    }
+
+    /// Decode lines which are not valid UTF-8 as Latin-1 instead of
+    /// failing. Each line decoded this way is reported in `warnings`.
+    pub fn lossy(mut self) -> RecordReader<R> {
+        self.lines.lossy = true;
+        self.options.lossy = true;
+        self
+    }
+
+    /// The problems with the input which did not stop it being read,
+    /// such as lines decoded as Latin-1 in lossy mode, or lines
+    /// skipped in lenient mode
+    pub fn warnings(&self) -> &[RecError] {
+        &self.lines.warnings
+    }
+
+    /// Carry on reading after errors instead of stopping at the
+    /// first one. The rest of a record containing an invalid line is
+    /// skipped, and the errors are reported by `diagnostics`.
+    pub fn recovering(mut self) -> RecordReader<R> {
+        self.recovering = true;
+        self
+    }
+
+    /// All of the errors recovered from and the warnings about the
+    /// input so far, in source order
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut out = self.errors.clone();
+        out.extend(self.warnings().iter().map(|w| Diagnostic::from_error(Severity::Warning, w)));
+        out.sort_by_key(|d| d.span.start_offset);
+        out
+    }
+
+    /// The descriptor for the records currently being read, if there
+    /// has been one
+    pub fn descriptor(&self) -> Option<&RecordDescriptor> {
+        self.descriptor.as_ref()
+    }
+
+    fn error_position(&self, line: usize, offset: usize, indent: usize) -> Position {
+        self.lines.position_at(line, offset + indent, indent + 1)
+    }
+
+    /// Read the next record or descriptor. A record containing a
+    /// `%rec` field is a descriptor, which changes the type of the
+    /// records after it.
+    pub fn next_entry(&mut self) -> Option<Result<Entry, RecError>> {
+        while !self.done {
+            match self.read_entry() {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => return None,
+                Err(e) => if self.recovering {
+                    self.recover(e);
+                } else {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            }
+        }
+        None
+    }
+
+    /// Read the next record without interpreting it, so that a
+    /// descriptor is returned as a plain record of its `%` fields and
+    /// doesn't change the type of the records after it
+    pub fn next_raw(&mut self) -> Option<Result<Record, RecError>> {
+        if self.done {
+            return None;
+        }
+        match self.read_record() {
+            Ok(Some(rec)) => Some(Ok(rec)),
+            Ok(None) => None,
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<Entry>, RecError> {
+        let rec = match self.read_record()? {
+            Some(rec) => rec,
+            None => return Ok(None),
+        };
+        self.last_span = rec.span;
+        let span = rec.span.unwrap_or_default();
+
+        if rec.fields.iter().any(|(k, _)| k == "%rec") {
+            let desc = RecordDescriptor::from_record(&rec);
+            if desc.is_err() {
+                // keep just the type of an invalid descriptor, so that
+                // the records after it aren't taken for the ones
+                // before it
+                self.descriptor = rec.get("%rec").ok()
+                    .and_then(|v| v.split_whitespace().next())
+                    .map(|t| RecordDescriptor {
+                        rec_type: t.to_owned(),
+                        span: rec.span,
+                        ..RecordDescriptor::default()
+                    });
+            }
+            match desc {
+                Ok(desc) => {
+                    self.descriptor = Some(desc.clone());
+                    Ok(Some(Entry::Descriptor(Box::new(desc))))
+                }
+                Err(RecError::InvalidDescriptor { message, .. }) => Err(RecError::InvalidDescriptor {
+                    message,
+                    pos: self.error_position(span.start, span.start_offset, 0),
+                }),
+                Err(e) => Err(e),
+            }
+        } else {
+            self.records += 1;
+            match self.options.max_records {
+                Some(max) if self.records > max => Err(RecError::LimitExceeded {
+                    what: "records".to_owned(),
+                    limit: max,
+                    pos: self.error_position(span.start, span.start_offset, 0),
+                }),
+                _ => Ok(Some(Entry::Record(rec))),
+            }
+        }
+    }
+
+    /// Report an error as a diagnostic and, if it happened partway
+    /// through a record, skip the rest of that record. Errors after
+    /// which nothing more can be read stop the reader.
+    fn recover(&mut self, err: RecError) {
+        let fatal = matches!(err, RecError::Io { .. } | RecError::LimitExceeded { .. });
+        let span = match err.position() {
+            Some(pos) if fatal => Span::at(pos),
+            Some(pos) if self.in_record => Span {
+                start: pos.line,
+                end: self.lines.line,
+                start_offset: pos.offset,
+                end_offset: self.lines.last_end,
+            },
+            _ => self.last_span.unwrap_or_default(),
+        };
+        self.errors.push(Diagnostic { span, ..Diagnostic::from_error(Severity::Error, &err) });
+
+        if fatal {
+            self.done = true;
+            return;
+        }
+        if self.in_record {
+            self.in_record = false;
+            loop {
+                match self.lines.next() {
+                    Some(Ok(ln)) => if ln.trim_start_matches(' ').is_empty() {
+                        break;
+                    },
+                    // bad bytes in the rest of the record don't matter
+                    Some(Err(_)) => match self.lines.error.take() {
+                        Some(RecError::InvalidEncoding { .. }) => (),
+                        Some(e) => return self.recover(e),
+                        None => (),
+                    },
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Read lines up to the end of the next non-empty record, giving
+    /// it the type of the descriptor in effect
+    fn read_record(&mut self) -> Result<Option<Record>, RecError> {
+        self.in_record = true;
+        let rec = parse_record(&mut self.lines, &self.options)?;
+        self.in_record = false;
+        Ok(rec.map(|rec| Record {
+            rec_type: self.descriptor.as_ref().map(|d| d.rec_type.clone()),
+            ..rec.into_record()
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for RecordReader<R> {
+    type Item = Result<Record, RecError>;
+
+    fn next(&mut self) -> Option<Result<Record, RecError>> {
+        loop {
+            match self.next_entry()? {
+                Ok(Entry::Record(rec)) => return Some(Ok(rec)),
+                Ok(Entry::Descriptor(_)) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// A reader for the records of a string, which borrows their field
+/// names and values from it where it can instead of copying them.
+/// This is what `RecfileRef` is parsed with.
+pub struct StrReader<'a> {
+    lines: StrLines<'a>,
+    options: ParseOptions,
+    /// Set after an error, so that nothing more is read
+    done: bool,
+}
+
+impl<'a> StrReader<'a> {
+    pub fn new(source: &'a str, options: &ParseOptions) -> StrReader<'a> {
+        let lines = StrLines {
+            rest: source,
+            line: 0,
+            offset: 0,
+            last_end: 0,
+            bom: 0,
+            max_line_length: options.max_line_length,
+            warnings: vec![],
+        };
+        StrReader { lines, options: options.clone(), done: false }
+    }
+
+    /// Read the next record. As with `RecordReader::next_raw`,
+    /// descriptors are returned as plain records, and no record is
+    /// given a type.
+    pub fn next_raw(&mut self) -> Option<Result<BorrowedRecord<'a>, RecError>> {
+        if self.done {
+            return None;
+        }
+        match parse_record(&mut self.lines, &self.options) {
+            Ok(Some(rec)) => Some(Ok(rec)),
+            Ok(None) => None,
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// The lines skipped in lenient mode so far
+    pub fn warnings(&self) -> &[RecError] {
+        &self.lines.warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::{ParseOptions, RecordReader};
+    use {Position, RecError, Recfile, Span};
+
+    #[test]
+    fn records_and_descriptors() {
+        let mut reader = RecordReader::new(
+            &b"Id: 0\n\n%rec: A\n%key: Id\n\nId: 1\n+ more\n\nId: 2\n"[..]);
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.rec_type, None);
+        assert!(reader.descriptor().is_none());
+
+        let second = reader.next().unwrap().unwrap();
+        assert_eq!(second.rec_type, Some("A".to_owned()));
+        assert_eq!(second.get("Id").unwrap(), "1\nmore");
+        assert_eq!(reader.descriptor().and_then(|d| d.key.clone()), Some("Id".to_owned()));
+
+        assert_eq!(reader.next().unwrap().unwrap().get("Id").unwrap(), "2");
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn stops_after_errors() {
+        let mut reader = RecordReader::new(&b"Id: 0\n\n  bad line\n\nId: 1\n"[..])
+            .with_file_name("test.rec");
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err, RecError::InvalidLine {
+            ln: "bad line".to_owned(),
+            pos: Position {
+                file: Some("test.rec".to_owned()),
+                line: 3,
+                column: 3,
+                offset: 9,
+            },
+        });
+        assert_eq!(err.to_string(), "test.rec:3:3: Invalid line: bad line");
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn descriptor_errors_have_positions() {
+        let mut reader = RecordReader::new(&b"Id: 0\n\n%rec: A\n%size: lots\n"[..])
+            .with_file_name("test.rec");
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.position(), Some(&Position {
+            file: Some("test.rec".to_owned()),
+            line: 3,
+            column: 1,
+            offset: 7,
+        }));
+        assert_eq!(err.to_string(), "test.rec:3:1: Invalid record descriptor: bad %size value: lots");
+    }
+
+    #[test]
+    fn invalid_descriptors_keep_their_type() {
+        let src = &b"%rec: A
+%mandatory: Title
+
+Title: x
+
+%rec: B
+%size: lots
+
+Id: 1
+"[..];
+        let mut reader = RecordReader::new(src).recovering();
+        let recs: Vec<_> = reader.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(recs[0].rec_type, Some("A".to_owned()));
+        assert_eq!(recs[1].rec_type, Some("B".to_owned()));
+        assert_eq!(reader.descriptor().map(|d| d.mandatory.len()), Some(0));
+        assert_eq!(reader.diagnostics().len(), 1);
+
+        let (file, diags) = Recfile::parse_recovering(src);
+        assert_eq!(diags.len(), 1);
+        assert!(file.check_integrity().is_empty());
+    }
+
+    #[test]
+    fn record_and_field_spans() {
+        let src = "# comment\nId: 0\r\nName: a\n+ b\n\nId: 1\n";
+        let mut reader = RecordReader::new(src.as_bytes());
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.span, Some(Span { start: 2, end: 4, start_offset: 10, end_offset: 28 }));
+        assert_eq!(first.field_span("Id"),
+                   Some(Span { start: 2, end: 2, start_offset: 10, end_offset: 15 }));
+        assert_eq!(first.field_span("Name"),
+                   Some(Span { start: 3, end: 4, start_offset: 17, end_offset: 28 }));
+        assert_eq!(&src[17..28], "Name: a\n+ b");
+
+        let second = reader.next().unwrap().unwrap();
+        assert_eq!(second.span, Some(Span { start: 6, end: 6, start_offset: 30, end_offset: 35 }));
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let src = &b"Id: 0\n\nId: 1\nName: Jos\xe9\n\nId: 2\n"[..];
+        let mut reader = RecordReader::new(src);
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(reader.next().unwrap(), Err(RecError::InvalidEncoding {
+            byte: 0xe9,
+            pos: Position { file: None, line: 4, column: 10, offset: 22 },
+        }));
+        assert!(reader.next().is_none());
+        assert!(Recfile::parse(src).is_err());
+
+        let mut reader = RecordReader::new(src).lossy();
+        let recs: Vec<_> = reader.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(recs.len(), 3);
+        assert_eq!(recs[1].get("Name").unwrap(), "José");
+        assert_eq!(reader.warnings().len(), 1);
+    }
+
+    /// A reader which fails after some number of bytes
+    struct Failing(&'static [u8]);
+
+    impl Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::other("disk on fire"));
+            }
+            let n = self.0.read(buf)?;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn io_errors_are_reported() {
+        let input = io::BufReader::new(Failing(b"Id: 0\n\nId: 1\n"));
+        let mut reader = RecordReader::new(input).with_file_name("f.rec");
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next().unwrap() {
+            Err(RecError::Io { kind, pos, .. }) => {
+                assert_eq!(kind, io::ErrorKind::Other);
+                assert_eq!((pos.line, pos.offset), (4, 13));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn byte_order_marks_and_crlf() {
+        let src = &b"\xef\xbb\xbfId: 0\r\nName: a\r\n\r\n"[..];
+        let mut reader = RecordReader::new(src);
+        let rec = reader.next().unwrap().unwrap();
+        assert_eq!(rec.fields[0], ("Id".to_owned(), "0".to_owned()));
+        assert_eq!(rec.get("Name").unwrap(), "a");
+        assert_eq!(rec.span, Some(Span { start: 1, end: 2, start_offset: 3, end_offset: 17 }));
+
+        let mut reader = RecordReader::new(&b"\xef\xbb\xbf oops\n"[..]);
+        match reader.next().unwrap() {
+            Err(RecError::InvalidLine { pos, .. }) => assert_eq!((pos.column, pos.offset), (2, 4)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strict_and_lenient_parsing() {
+        let src = &b"Id: 1\nBad Name: x\n  Indented: y\n\noops\nId: 2\n"[..];
+        assert!(Recfile::parse(src).is_err());
+
+        let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+        match Recfile::parse_with(src, &strict) {
+            Err(RecError::InvalidFieldName { name, pos }) => {
+                assert_eq!(name, "Bad Name");
+                assert_eq!((pos.line, pos.column), (2, 1));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let lenient = ParseOptions { lenient: true, ..ParseOptions::default() };
+        let (file, warnings) = Recfile::parse_with(src, &lenient).unwrap();
+        assert_eq!(file.records.len(), 2);
+        assert_eq!(file.records[0].fields.len(), 3);
+        assert_eq!(warnings, vec![RecError::InvalidLine {
+            ln: "oops".to_owned(),
+            pos: Position { file: None, line: 5, column: 1, offset: 33 },
+        }]);
+
+        let both = ParseOptions { strict: true, lenient: true, ..ParseOptions::default() };
+        let (file, warnings) = Recfile::parse_with(src, &both).unwrap();
+        assert_eq!(file.records[0].fields, vec![("Id".to_owned(), "1".to_owned())]);
+        assert_eq!(warnings.len(), 3);
+    }
+
+    #[test]
+    fn limits() {
+        let src = &b"Id: 1\nA: 1\n\nId: 2\n\nId: 3\nLong: xxxxxxxxxx\n"[..];
+        let limited = |options: ParseOptions| match Recfile::parse_with(src, &options) {
+            Err(RecError::LimitExceeded { what, limit, pos }) => (what, limit, pos.line),
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(limited(ParseOptions { max_records: Some(2), ..ParseOptions::default() }),
+                   ("records".to_owned(), 2, 6));
+        assert_eq!(limited(ParseOptions { max_fields: Some(1), ..ParseOptions::default() }),
+                   ("fields in a record".to_owned(), 1, 2));
+        assert_eq!(limited(ParseOptions { max_line_length: Some(8), ..ParseOptions::default() }),
+                   ("bytes in a line".to_owned(), 8, 7));
+
+        let roomy = ParseOptions {
+            max_records: Some(3),
+            max_fields: Some(2),
+            max_line_length: Some(16),
+            ..ParseOptions::default()
+        };
+        assert_eq!(Recfile::parse_with(src, &roomy).unwrap().0.records.len(), 3);
+    }
+}
@@ -0,0 +1,138 @@
+use {Expr, RecError, Recfile, Record};
+
+/// A `Selection` picks out the records that a tool like `rr-del` or
+/// `rr-set` should operate on. Every criterion which is present must
+/// hold for a record to be selected.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Selection {
+    /// Only select records of this type
+    pub rec_type: Option<String>,
+    /// Only select records matching this selection expression
+    pub expr: Option<Expr>,
+    /// Only select records at these (inclusive) ranges of positions,
+    /// counting from zero among the records of the selected type
+    pub indexes: Option<Vec<(usize, usize)>>,
+    /// Only select records with a field value containing this string
+    pub quick: Option<String>,
+}
+
+impl Selection {
+    /// Parse a comma-separated list of indexes and ranges of
+    /// indexes, e.g. `0,3-5`
+    pub fn parse_indexes(s: &str) -> Result<Vec<(usize, usize)>, RecError> {
+        let bad = || RecError::GenericError {
+            message: format!("bad index list: {}", s),
+        };
+        let index = |n: &str| n.trim().parse::<usize>().map_err(|_| bad());
+
+        let mut ranges = vec![];
+        for part in s.split(',') {
+            let range = match part.find('-') {
+                Some(pos) => (index(&part[..pos])?, index(&part[pos + 1..])?),
+                None => (index(part)?, index(part)?),
+            };
+            if range.0 > range.1 {
+                return Err(bad());
+            }
+            ranges.push(range);
+        }
+        Ok(ranges)
+    }
+
+    /// Returns `true` if no criteria have been given, in which case
+    /// every record is selected
+    pub fn is_empty(&self) -> bool {
+        *self == Selection::default()
+    }
+
+    fn matches(&self, index: usize, rec: &Record) -> Result<bool, RecError> {
+        if let Some(ref ranges) = self.indexes {
+            if !ranges.iter().any(|&(lo, hi)| lo <= index && index <= hi) {
+                return Ok(false);
+            }
+        }
+        if let Some(ref q) = self.quick {
+            if !rec.fields.iter().any(|(_, v)| v.contains(q.as_str())) {
+                return Ok(false);
+            }
+        }
+        match self.expr {
+            Some(ref e) => e.matches(rec),
+            None => Ok(true),
+        }
+    }
+}
+
+impl Recfile {
+    /// Return the records picked out by a selection, in the order
+    /// they appear in the file
+    pub fn select<'a>(&'a self, sel: &'a Selection) -> Result<Vec<&'a Record>, RecError> {
+        Ok(self.select_indexes(sel)?.into_iter().map(|i| &self.records[i]).collect())
+    }
+
+    /// Return the positions in `records` of the records picked out by
+    /// a selection, in ascending order
+    pub fn select_indexes(&self, sel: &Selection) -> Result<Vec<usize>, RecError> {
+        let candidates = self.records.iter().enumerate().filter(|(_, r)| match sel.rec_type {
+            Some(ref t) => r.rec_type.as_ref() == Some(t),
+            None => true,
+        });
+
+        let mut selected = vec![];
+        for (n, (i, r)) in candidates.enumerate() {
+            if sel.matches(n, r)? {
+                selected.push(i);
+            }
+        }
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Selection;
+    use {Expr, Recfile};
+
+    fn titles(sel: &Selection) -> Vec<String> {
+        let file = Recfile::parse(&b"Title: x
+
+%rec: Book
+
+Title: a
+Year: 1990
+
+Title: b
+Year: 2001
+
+Title: c
+Year: 2010
+"[..]).unwrap();
+        file.select(sel).unwrap()
+            .iter()
+            .map(|r| r.get("Title").unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn index_lists() {
+        assert_eq!(Selection::parse_indexes("0,3-5").unwrap(), vec![(0, 0), (3, 5)]);
+        assert!(Selection::parse_indexes("5-3").is_err());
+        assert!(Selection::parse_indexes("one").is_err());
+    }
+
+    #[test]
+    fn select_by_criteria() {
+        assert_eq!(titles(&Selection::default()), vec!["x", "a", "b", "c"]);
+
+        let books = Selection {
+            rec_type: Some("Book".to_owned()),
+            ..Selection::default()
+        };
+        assert_eq!(titles(&Selection { indexes: Some(vec![(1, 2)]), ..books.clone() }),
+                   vec!["b", "c"]);
+        assert_eq!(titles(&Selection { quick: Some("200".to_owned()), ..books.clone() }),
+                   vec!["b"]);
+        assert_eq!(titles(&Selection { expr: Some(Expr::parse("Year > 2000").unwrap()), ..books }),
+                   vec!["b", "c"]);
+    }
+}
@@ -0,0 +1,635 @@
+//! Serializing Rust values into `Record`s with serde. This is the
+//! inverse of `de`: structs and maps become records with their
+//! fields in order, sequences become repeated fields, `None` values
+//! are left out, and a field named `%rec` sets the record's type.
+
+use serde::ser::{self, Impossible, Serialize};
+
+pub use de::Error;
+use de::TYPE_FIELD;
+use reader::valid_field_name;
+use {RecError, Recfile, Record, RecordDescriptor};
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Error {
+        Error(RecError::ConversionError { message: msg.to_string() })
+    }
+}
+
+fn unsupported(what: &str) -> Error {
+    Error(RecError::ConversionError {
+        message: format!("cannot serialize {} as a field value", what),
+    })
+}
+
+/// Serialize a struct or map into a record
+pub fn to_record<T: Serialize + ?Sized>(value: &T) -> Result<Record, RecError> {
+    value.serialize(RecordSerializer).map_err(|Error(e)| e)
+}
+
+/// Serialize a list of structs or maps into a recfile of records of
+/// the given type, along with a (minimal) descriptor for that type
+pub fn to_recfile<T: Serialize>(values: &[T], rec_type: &str) -> Result<Recfile, RecError> {
+    let mut records = vec![];
+    for v in values.iter() {
+        let mut rec = to_record(v)?;
+        rec.rec_type = Some(rec_type.to_owned());
+        records.push(rec);
+    }
+    Ok(Recfile {
+        records,
+        descriptors: vec![RecordDescriptor {
+            rec_type: rec_type.to_owned(),
+            ..RecordDescriptor::default()
+        }],
+    })
+}
+
+/// A serde `Serializer` which produces a `Record` from a struct or
+/// map
+pub struct RecordSerializer;
+
+impl ser::Serializer for RecordSerializer {
+    type Ok = Record;
+    type Error = Error;
+    type SerializeSeq = Impossible<Record, Error>;
+    type SerializeTuple = Impossible<Record, Error>;
+    type SerializeTupleStruct = Impossible<Record, Error>;
+    type SerializeTupleVariant = Impossible<Record, Error>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = StructSerializer;
+    type SerializeStructVariant = Impossible<Record, Error>;
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<StructSerializer, Error> {
+        Ok(StructSerializer { rec: Record::default() })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer { rec: Record::default(), key: None })
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Record, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Record, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_i8(self, _v: i8) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_i16(self, _v: i16) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_i32(self, _v: i32) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_i64(self, _v: i64) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_u8(self, _v: u8) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_u16(self, _v: u16) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_u32(self, _v: u32) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_u64(self, _v: u64) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_f32(self, _v: f32) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_f64(self, _v: f64) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_char(self, _v: char) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_str(self, _v: &str) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_none(self) -> Result<Record, Error> { Err(not_a_record()) }
+    fn serialize_unit(self) -> Result<Record, Error> { Err(not_a_record()) }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Record, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+    ) -> Result<Record, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Record, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(not_a_record())
+    }
+}
+
+fn not_a_record() -> Error {
+    Error(RecError::ConversionError {
+        message: "only structs and maps can be serialized as records".to_owned(),
+    })
+}
+
+/// Check that a struct field or map key can be used as a field name
+fn check_name(name: &str) -> Result<(), Error> {
+    if valid_field_name(name) {
+        Ok(())
+    } else {
+        Err(Error(RecError::ConversionError { message: format!("invalid field name: {}", name) }))
+    }
+}
+
+/// Add a field to a record, or set its type if the field is `%rec`
+fn add_field<T: Serialize + ?Sized>(rec: &mut Record, name: &str, value: &T) -> Result<(), Error> {
+    if name == TYPE_FIELD {
+        rec.rec_type = value.serialize(ScalarSerializer)?;
+        Ok(())
+    } else {
+        check_name(name)?;
+        value.serialize(FieldSerializer { name, fields: &mut rec.fields })
+    }
+}
+
+pub struct StructSerializer {
+    rec: Record,
+}
+
+impl ser::SerializeStruct for StructSerializer {
+    type Ok = Record;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        add_field(&mut self.rec, key, value)
+    }
+
+    fn end(self) -> Result<Record, Error> {
+        Ok(self.rec)
+    }
+}
+
+pub struct MapSerializer {
+    rec: Record,
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Record;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        match key.serialize(ScalarSerializer)? {
+            Some(k) => {
+                check_name(&k)?;
+                self.key = Some(k);
+                Ok(())
+            }
+            None => Err(unsupported("an empty key")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        match self.key.take() {
+            Some(k) => add_field(&mut self.rec, &k, value),
+            None => Err(ser::Error::custom("value serialized before key")),
+        }
+    }
+
+    fn end(self) -> Result<Record, Error> {
+        Ok(self.rec)
+    }
+}
+
+/// Serializes the value of one field, adding a field to the record
+/// for each value of a sequence and none at all for `None`
+struct FieldSerializer<'a> {
+    name: &'a str,
+    fields: &'a mut Vec<(String, String)>,
+}
+
+impl<'a> FieldSerializer<'a> {
+    fn push(self, value: Option<String>) -> Result<(), Error> {
+        if let Some(v) = value {
+            self.fields.push((self.name.to_owned(), v));
+        }
+        Ok(())
+    }
+
+    fn push_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        if let Some(v) = value.serialize(ScalarSerializer)? {
+            self.fields.push((self.name.to_owned(), v));
+        }
+        Ok(())
+    }
+}
+
+macro_rules! push_scalar {
+    ($($method:ident: $typ:ty,)*) => {
+        $(
+            fn $method(self, v: $typ) -> Result<(), Error> {
+                let value = ScalarSerializer.$method(v)?;
+                self.push(value)
+            }
+        )*
+    }
+}
+
+impl<'a> ser::Serializer for FieldSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    push_scalar! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+        serialize_bytes: &[u8],
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.push(Some(String::new()))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.push(Some(String::new()))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.push(Some(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(unsupported("a map"))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(unsupported(name))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+}
+
+impl<'a> ser::SerializeSeq for FieldSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTuple for FieldSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for FieldSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Serializes a single value into the text of a field, or `None` if
+/// the field should be left out
+struct ScalarSerializer;
+
+macro_rules! display_scalar {
+    ($($method:ident: $typ:ty,)*) => {
+        $(
+            fn $method(self, v: $typ) -> Result<Option<String>, Error> {
+                Ok(Some(v.to_string()))
+            }
+        )*
+    }
+}
+
+impl ser::Serializer for ScalarSerializer {
+    type Ok = Option<String>;
+    type Error = Error;
+    type SerializeSeq = Impossible<Option<String>, Error>;
+    type SerializeTuple = Impossible<Option<String>, Error>;
+    type SerializeTupleStruct = Impossible<Option<String>, Error>;
+    type SerializeTupleVariant = Impossible<Option<String>, Error>;
+    type SerializeMap = Impossible<Option<String>, Error>;
+    type SerializeStruct = Impossible<Option<String>, Error>;
+    type SerializeStructVariant = Impossible<Option<String>, Error>;
+
+    display_scalar! {
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<Option<String>, Error> {
+        Ok(Some(if v { "yes" } else { "no" }.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Option<String>, Error> {
+        match std::str::from_utf8(v) {
+            Ok(s) => Ok(Some(s.to_owned())),
+            Err(_) => Err(unsupported("bytes which aren't valid UTF-8")),
+        }
+    }
+
+    fn serialize_none(self) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Option<String>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<String>, Error> {
+        Ok(Some(String::new()))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<String>, Error> {
+        Ok(Some(String::new()))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Option<String>, Error> {
+        Ok(Some(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Option<String>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Option<String>, Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(unsupported("a nested sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(unsupported("a nested tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(unsupported(name))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(unsupported("a map"))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(unsupported(name))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_record, to_recfile};
+    use {from_record, RecError, Recfile, Record};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Book {
+        #[serde(rename = "Title")]
+        title: String,
+        #[serde(rename = "Author", default)]
+        authors: Vec<String>,
+        #[serde(rename = "Year")]
+        year: Option<u32>,
+        #[serde(rename = "Read")]
+        read: bool,
+        #[serde(rename = "Note")]
+        note: Option<String>,
+    }
+
+    fn book() -> Book {
+        Book {
+            title: "GNU Emacs Manual".to_owned(),
+            authors: vec!["Richard M. Stallman".to_owned(), "Others".to_owned()],
+            year: None,
+            read: true,
+            note: Some("first line\nsecond line".to_owned()),
+        }
+    }
+
+    #[test]
+    fn structs() {
+        let rec = to_record(&book()).unwrap();
+        assert_eq!(rec, Record::new(None, vec![
+            ("Title", "GNU Emacs Manual"),
+            ("Author", "Richard M. Stallman"),
+            ("Author", "Others"),
+            ("Read", "yes"),
+            ("Note", "first line\nsecond line"),
+        ]));
+        assert_eq!(from_record::<Book>(&rec).unwrap(), book());
+    }
+
+    #[test]
+    fn recfiles() {
+        let file = to_recfile(&[book()], "Book").unwrap();
+        let text = file.to_string().unwrap();
+        assert_eq!(text, "%rec: Book
+
+Title: GNU Emacs Manual
+Author: Richard M. Stallman
+Author: Others
+Read: yes
+Note: first line
++ second line
+
+");
+        assert_eq!(Recfile::parse(text.as_bytes()).unwrap().records, file.records);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(to_record(&"just a string").is_err());
+        let mut nested = ::std::collections::BTreeMap::new();
+        nested.insert("Nested", vec![vec![1]]);
+        assert!(to_record(&nested).is_err());
+
+        let mut bad_key = ::std::collections::BTreeMap::new();
+        bad_key.insert("Bad Name", 1);
+        assert_eq!(to_record(&bad_key), Err(RecError::ConversionError {
+            message: "invalid field name: Bad Name".to_owned(),
+        }));
+        let mut with_type = ::std::collections::BTreeMap::new();
+        with_type.insert("%rec", "Book");
+        with_type.insert("Id_2", "1");
+        assert!(to_record(&with_type).is_ok());
+    }
+}
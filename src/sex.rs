@@ -0,0 +1,661 @@
+//! Selection expressions, which use the same syntax as the `-e`
+//! option of GNU `recsel`: for example, `Location = 'home' && #Author > 1`.
+
+use regex::Regex;
+
+use types::{parse_int, parse_real, TypeRegex};
+use {date, RecError, Recfile, Record};
+
+/// A `Value` is the result of evaluating an expression
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Real(f64),
+    Str(String),
+}
+
+impl Value {
+    fn from_bool(b: bool) -> Value {
+        Value::Int(if b { 1 } else { 0 })
+    }
+
+    /// Interpret this value as a boolean: numbers are true if they
+    /// are non-zero, and strings are true if they are non-empty and
+    /// not a number equal to zero
+    pub fn as_bool(&self) -> bool {
+        match *self {
+            Value::Int(n) => n != 0,
+            Value::Real(n) => n != 0.0,
+            Value::Str(ref s) => match to_number(s) {
+                Some(n) => n.as_bool(),
+                None => !s.is_empty(),
+            },
+        }
+    }
+
+    /// Interpret this value as a number. As in GNU recutils, strings
+    /// that don't look like numbers are treated as zero.
+    fn as_number(&self) -> Value {
+        match *self {
+            Value::Str(ref s) => to_number(s).unwrap_or(Value::Int(0)),
+            ref n => n.clone(),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        match *self {
+            Value::Str(ref s) => to_number(s).is_some(),
+            _ => true,
+        }
+    }
+
+    fn as_real(&self) -> f64 {
+        match self.as_number() {
+            Value::Int(n) => n as f64,
+            Value::Real(n) => n,
+            Value::Str(_) => 0.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Real(n) => write!(f, "{}", n),
+            Value::Str(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+fn to_number(s: &str) -> Option<Value> {
+    parse_int(s).map(Value::Int)
+        .or_else(|| parse_real(s).map(Value::Real))
+}
+
+/// The binary operators, listed from lowest to highest precedence
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum BinOp {
+    Implies,
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    /// `<<`: the left date is before the right one
+    Before,
+    /// `>>`: the left date is after the right one
+    After,
+    /// `==`: the two dates are the same point in time
+    SameTime,
+    Add,
+    Sub,
+    Concat,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// A parsed selection expression
+#[derive(PartialEq, Debug, Clone)]
+pub enum Expr {
+    Int(i64),
+    Real(f64),
+    Str(String),
+    /// The (first) value of a field
+    Field(String),
+    /// `Field[n]`: the nth value of a field, counting from zero
+    Subscript(String, usize),
+    /// `#Field`: the number of times a field appears
+    Count(String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// `~`: the left-hand side matches the regular expression
+    Match(Box<Expr>, TypeRegex),
+    /// `c ? a : b`
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Token {
+    Int(i64),
+    Real(f64),
+    Str(String),
+    Name(String),
+    Op(&'static str),
+}
+
+fn bad_expr(message: String) -> RecError {
+    RecError::InvalidExpression { message }
+}
+
+/// These are ordered so that longer operators are tried before their
+/// prefixes
+const OPERATORS: [&str; 26] = [
+    "&&", "||", "=>", "==", "!=", "<=", ">=", "<<", ">>",
+    "=", "<", ">", "!", "~", "&", "+", "-", "*", "/", "%",
+    "?", ":", "#", "(", ")", "[",
+];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, RecError> {
+    let mut toks = vec![];
+    let mut rest = s;
+
+    loop {
+        rest = rest.trim_start();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => return Ok(toks),
+        };
+
+        if c == '\'' || c == '"' {
+            let mut val = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, q)) if q == c => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => val.push('\n'),
+                        Some((_, 't')) => val.push('\t'),
+                        Some((_, e)) => val.push(e),
+                        None => return Err(bad_expr("unterminated string".to_owned())),
+                    },
+                    Some((_, ch)) => val.push(ch),
+                    None => return Err(bad_expr("unterminated string".to_owned())),
+                }
+            };
+            toks.push(Token::Str(val));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+                .unwrap_or(rest.len());
+            let lit = &rest[..end];
+            if let Some(n) = parse_int(lit) {
+                toks.push(Token::Int(n));
+            } else if let Some(n) = parse_real(lit) {
+                toks.push(Token::Real(n));
+            } else {
+                return Err(bad_expr(format!("bad number: {}", lit)));
+            }
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic()
+            || (c == '%' && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())) {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map(|n| n + 1)
+                .unwrap_or(rest.len());
+            toks.push(Token::Name(rest[..end].to_owned()));
+            rest = &rest[end..];
+        } else if c == ']' {
+            toks.push(Token::Op("]"));
+            rest = &rest[1..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            toks.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(bad_expr(format!("unexpected character: {}", c)));
+        }
+    }
+}
+
+struct Parser {
+    toks: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.toks.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(&Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), RecError> {
+        match self.next() {
+            Some(Token::Op(o)) if o == op => Ok(()),
+            Some(t) => Err(bad_expr(format!("expected {} but found {:?}", op, t))),
+            None => Err(bad_expr(format!("expected {} at end of expression", op))),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<Expr, RecError> {
+        let cond = self.binary(0)?;
+        if self.peek_op() == Some("?") {
+            self.next();
+            let then = self.ternary()?;
+            self.expect(":")?;
+            let els = self.ternary()?;
+            Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(els)))
+        } else {
+            Ok(cond)
+        }
+    }
+
+    /// Parse a chain of binary operators at the given precedence
+    /// level or higher
+    fn binary(&mut self, level: usize) -> Result<Expr, RecError> {
+        const LEVELS: [&[(&str, Option<BinOp>)]; 7] = [
+            &[("=>", Some(BinOp::Implies))],
+            &[("||", Some(BinOp::Or))],
+            &[("&&", Some(BinOp::And))],
+            &[
+                ("=", Some(BinOp::Eq)), ("!=", Some(BinOp::Ne)),
+                ("<", Some(BinOp::Lt)), (">", Some(BinOp::Gt)),
+                ("<=", Some(BinOp::Le)), (">=", Some(BinOp::Ge)),
+                ("<<", Some(BinOp::Before)), (">>", Some(BinOp::After)),
+                ("==", Some(BinOp::SameTime)),
+            ],
+            &[("~", None)],
+            &[("+", Some(BinOp::Add)), ("-", Some(BinOp::Sub)), ("&", Some(BinOp::Concat))],
+            &[("*", Some(BinOp::Mul)), ("/", Some(BinOp::Div)), ("%", Some(BinOp::Mod))],
+        ];
+
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op() {
+            let binop = match LEVELS[level].iter().find(|&&(o, _)| o == op) {
+                Some(&(_, binop)) => binop,
+                None => break,
+            };
+            self.next();
+            lhs = match binop {
+                Some(binop) => {
+                    let rhs = self.binary(level + 1)?;
+                    Expr::Binary(binop, Box::new(lhs), Box::new(rhs))
+                }
+                None => match self.next() {
+                    Some(Token::Str(re)) => {
+                        let re = Regex::new(&re)
+                            .map_err(|e| bad_expr(format!("bad regex {}: {}", re, e)))?;
+                        Expr::Match(Box::new(lhs), TypeRegex(re))
+                    }
+                    _ => return Err(bad_expr("~ must be followed by a string".to_owned())),
+                },
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, RecError> {
+        match self.peek_op() {
+            Some("!") => {
+                self.next();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some("-") => {
+                self.next();
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            }
+            Some("#") => {
+                self.next();
+                match self.next() {
+                    Some(Token::Name(n)) => Ok(Expr::Count(n)),
+                    _ => Err(bad_expr("# must be followed by a field name".to_owned())),
+                }
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, RecError> {
+        match self.next() {
+            Some(Token::Int(n)) => Ok(Expr::Int(n)),
+            Some(Token::Real(n)) => Ok(Expr::Real(n)),
+            Some(Token::Str(s)) => Ok(Expr::Str(s)),
+            Some(Token::Name(n)) => {
+                if self.peek_op() == Some("[") {
+                    self.next();
+                    let idx = match self.next() {
+                        Some(Token::Int(i)) if i >= 0 => i as usize,
+                        _ => return Err(bad_expr(format!("bad subscript for {}", n))),
+                    };
+                    self.expect("]")?;
+                    Ok(Expr::Subscript(n, idx))
+                } else {
+                    Ok(Expr::Field(n))
+                }
+            }
+            Some(Token::Op("(")) => {
+                let e = self.ternary()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(t) => Err(bad_expr(format!("unexpected {:?}", t))),
+            None => Err(bad_expr("unexpected end of expression".to_owned())),
+        }
+    }
+}
+
+/// Compare two numeric values, using integer comparison when we can
+fn compare_numbers(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a.as_number(), b.as_number()) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(&y)),
+        (x, y) => x.as_real().partial_cmp(&y.as_real()),
+    }
+}
+
+fn arith(op: BinOp, a: &Value, b: &Value) -> Result<Value, RecError> {
+    match (a.as_number(), b.as_number()) {
+        (Value::Int(x), Value::Int(y)) => Ok(Value::Int(match op {
+            BinOp::Add => x.wrapping_add(y),
+            BinOp::Sub => x.wrapping_sub(y),
+            BinOp::Mul => x.wrapping_mul(y),
+            BinOp::Div | BinOp::Mod if y == 0 =>
+                return Err(bad_expr("division by zero".to_owned())),
+            BinOp::Div => x.wrapping_div(y),
+            _ => x.wrapping_rem(y),
+        })),
+        (x, y) => {
+            let (x, y) = (x.as_real(), y.as_real());
+            Ok(Value::Real(match op {
+                BinOp::Add => x + y,
+                BinOp::Sub => x - y,
+                BinOp::Mul => x * y,
+                BinOp::Div | BinOp::Mod if y == 0.0 =>
+                    return Err(bad_expr("division by zero".to_owned())),
+                BinOp::Div => x / y,
+                _ => x % y,
+            }))
+        }
+    }
+}
+
+/// The values that field references should use while evaluating: for
+/// each field listed, which of its values to pick
+type Choice<'a> = [(&'a str, usize)];
+
+fn nth_value<'a>(rec: &'a Record, name: &str, n: usize) -> &'a str {
+    rec.get_all(name).get(n).cloned().unwrap_or("")
+}
+
+impl Expr {
+    /// Parse a selection expression
+    pub fn parse(s: &str) -> Result<Expr, RecError> {
+        let mut p = Parser { toks: tokenize(s)?, pos: 0 };
+        let e = p.ternary()?;
+        match p.next() {
+            None => Ok(e),
+            Some(t) => Err(bad_expr(format!("unexpected {:?}", t))),
+        }
+    }
+
+    /// Evaluate this expression against a record. A field reference
+    /// without a subscript refers to the first value of that field,
+    /// and missing fields are treated as the empty string.
+    pub fn eval(&self, rec: &Record) -> Result<Value, RecError> {
+        self.eval_with(rec, &[])
+    }
+
+    /// Returns `true` if this expression holds for the record. As in
+    /// GNU recutils, if a field referenced without a subscript
+    /// appears several times in the record, then the expression is
+    /// tried with every combination of values of those fields and
+    /// holds if any of them does.
+    pub fn matches(&self, rec: &Record) -> Result<bool, RecError> {
+        let mut names = vec![];
+        self.field_names(&mut names);
+        let counts: Vec<usize> = names.iter()
+            .map(|n| rec.get_all(n).len().max(1))
+            .collect();
+
+        let mut idx = vec![0; names.len()];
+        loop {
+            let choice: Vec<(&str, usize)> = names.iter()
+                .cloned()
+                .zip(idx.iter().cloned())
+                .collect();
+            if self.eval_with(rec, &choice)?.as_bool() {
+                return Ok(true);
+            }
+
+            // step to the next combination, like an odometer
+            let mut i = 0;
+            loop {
+                if i == idx.len() {
+                    return Ok(false);
+                }
+                idx[i] += 1;
+                if idx[i] < counts[i] {
+                    break;
+                }
+                idx[i] = 0;
+                i += 1;
+            }
+        }
+    }
+
+    /// Collect the names of fields referenced without a subscript
+    fn field_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match *self {
+            Expr::Field(ref n) if !names.contains(&n.as_str()) => names.push(n),
+            Expr::Not(ref e) | Expr::Neg(ref e) | Expr::Match(ref e, _) =>
+                e.field_names(names),
+            Expr::Binary(_, ref a, ref b) => {
+                a.field_names(names);
+                b.field_names(names);
+            }
+            Expr::Cond(ref c, ref a, ref b) => {
+                c.field_names(names);
+                a.field_names(names);
+                b.field_names(names);
+            }
+            _ => (),
+        }
+    }
+
+    fn eval_with(&self, rec: &Record, choice: &Choice) -> Result<Value, RecError> {
+        Ok(match *self {
+            Expr::Int(n) => Value::Int(n),
+            Expr::Real(n) => Value::Real(n),
+            Expr::Str(ref s) => Value::Str(s.clone()),
+            Expr::Field(ref n) => {
+                let i = choice.iter()
+                    .find(|&&(c, _)| c == n)
+                    .map(|&(_, i)| i)
+                    .unwrap_or(0);
+                Value::Str(nth_value(rec, n, i).to_owned())
+            }
+            Expr::Subscript(ref n, i) => Value::Str(nth_value(rec, n, i).to_owned()),
+            Expr::Count(ref n) =>
+                Value::Int(rec.get_all(n).len() as i64),
+            Expr::Not(ref e) => Value::from_bool(!e.eval_with(rec, choice)?.as_bool()),
+            Expr::Neg(ref e) => arith(BinOp::Sub, &Value::Int(0), &e.eval_with(rec, choice)?)?,
+            Expr::Match(ref e, ref re) =>
+                Value::from_bool(re.0.is_match(&e.eval_with(rec, choice)?.to_string())),
+            Expr::Cond(ref c, ref a, ref b) => {
+                if c.eval_with(rec, choice)?.as_bool() {
+                    a.eval_with(rec, choice)?
+                } else {
+                    b.eval_with(rec, choice)?
+                }
+            }
+            Expr::Binary(op, ref a, ref b) => {
+                let x = a.eval_with(rec, choice)?;
+                // the logical operators short-circuit
+                match op {
+                    BinOp::Or if x.as_bool() => return Ok(Value::Int(1)),
+                    BinOp::And | BinOp::Implies if !x.as_bool() =>
+                        return Ok(Value::from_bool(op == BinOp::Implies)),
+                    _ => (),
+                }
+                let y = b.eval_with(rec, choice)?;
+                binary(op, &x, &y)?
+            }
+        })
+    }
+}
+
+impl Recfile {
+    /// Modify this Recfile in-place by only keeping the records
+    /// which match the expression
+    pub fn filter_by_expr(&mut self, expr: &Expr) -> Result<(), RecError> {
+        let mut kept = Vec::with_capacity(self.records.len());
+        for r in std::mem::take(&mut self.records) {
+            if expr.matches(&r)? {
+                kept.push(r);
+            }
+        }
+        self.records = kept;
+        Ok(())
+    }
+}
+
+fn binary(op: BinOp, x: &Value, y: &Value) -> Result<Value, RecError> {
+    use std::cmp::Ordering;
+
+    let numeric = x.is_numeric() && y.is_numeric();
+    let ordering = || if numeric {
+        compare_numbers(x, y)
+    } else {
+        Some(x.to_string().cmp(&y.to_string()))
+    };
+    let dates = || match (date::parse(&x.to_string()), date::parse(&y.to_string())) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        _ => None,
+    };
+
+    Ok(match op {
+        BinOp::Or | BinOp::And | BinOp::Implies => Value::from_bool(y.as_bool()),
+        BinOp::Eq => Value::from_bool(ordering() == Some(Ordering::Equal)),
+        BinOp::Ne => Value::from_bool(ordering() != Some(Ordering::Equal)),
+        BinOp::Lt => Value::from_bool(ordering() == Some(Ordering::Less)),
+        BinOp::Gt => Value::from_bool(ordering() == Some(Ordering::Greater)),
+        BinOp::Le => Value::from_bool(
+            matches!(ordering(), Some(Ordering::Less) | Some(Ordering::Equal))),
+        BinOp::Ge => Value::from_bool(
+            matches!(ordering(), Some(Ordering::Greater) | Some(Ordering::Equal))),
+        BinOp::Before => Value::from_bool(dates() == Some(Ordering::Less)),
+        BinOp::After => Value::from_bool(dates() == Some(Ordering::Greater)),
+        BinOp::SameTime => Value::from_bool(dates() == Some(Ordering::Equal)),
+        BinOp::Concat => Value::Str(format!("{}{}", x, y)),
+        BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod =>
+            arith(op, x, y)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinOp, Expr, Value};
+    use Record;
+
+    fn book() -> Record {
+        Record::new(Some("Book"), vec![
+            ("Title", "chapters.gnu.org administration guide"),
+            ("Author", "Nacho Gonzalez"),
+            ("Author", "Jose E. Marchesi"),
+            ("Location", "unknown"),
+            ("Pages", "120"),
+            ("Published", "2011-04-20"),
+        ])
+    }
+
+    fn eval(s: &str) -> Value {
+        Expr::parse(s).unwrap().eval(&book()).unwrap()
+    }
+
+    fn matches(s: &str) -> bool {
+        Expr::parse(s).unwrap().matches(&book()).unwrap()
+    }
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            Expr::parse("1 + 2 * 3").unwrap(),
+            Expr::Binary(
+                BinOp::Add,
+                Box::new(Expr::Int(1)),
+                Box::new(Expr::Binary(BinOp::Mul, Box::new(Expr::Int(2)), Box::new(Expr::Int(3)))),
+            ));
+        assert_eq!(eval("1 + 2 * 3"), Value::Int(7));
+        assert_eq!(eval("(1 + 2) * 3"), Value::Int(9));
+        assert_eq!(eval("7 / 2"), Value::Int(3));
+        assert_eq!(eval("7 / 2.0"), Value::Real(3.5));
+        assert_eq!(eval("7 % 2 - -1"), Value::Int(2));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Expr::parse("").is_err());
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("(1").is_err());
+        assert!(Expr::parse("'abc").is_err());
+        assert!(Expr::parse("Title ~ Author").is_err());
+        assert!(Expr::parse("Title ~ '('").is_err());
+        assert!(Expr::parse("Author[x]").is_err());
+        assert!(Expr::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(eval("Location"), Value::Str("unknown".to_owned()));
+        assert_eq!(eval("Author[1]"), Value::Str("Jose E. Marchesi".to_owned()));
+        assert_eq!(eval("Author[5]"), Value::Str("".to_owned()));
+        assert_eq!(eval("#Author"), Value::Int(2));
+        assert_eq!(eval("#Publisher"), Value::Int(0));
+        assert_eq!(eval("Pages + 1"), Value::Int(121));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(matches("Location = 'unknown'"));
+        assert!(matches("Location != \"home\""));
+        assert!(matches("Pages > 100 && Pages <= 120"));
+        assert!(matches("Pages = 120.0"));
+        assert!(matches("Title > 'a'"));
+        assert!(!matches("Publisher"));
+        assert!(matches("!Publisher"));
+        assert!(matches("Title ~ 'gnu\\\\.org'"));
+        assert!(!matches("Title ~ '^gnu'"));
+    }
+
+    #[test]
+    fn logic() {
+        assert!(matches("#Publisher || #Author"));
+        assert!(matches("Location = 'home' => #Publisher"));
+        assert!(!matches("Location = 'unknown' => #Publisher"));
+        assert_eq!(eval("#Author > 1 ? 'many' : 'one'"), Value::Str("many".to_owned()));
+        assert_eq!(eval("Title & ' by ' & Author"),
+                   Value::Str("chapters.gnu.org administration guide by Nacho Gonzalez".to_owned()));
+    }
+
+    #[test]
+    fn dates() {
+        assert!(matches("Published << '2012-01-01'"));
+        assert!(matches("Published >> '20 March 2011'"));
+        assert!(matches("Published == '20 April 2011'"));
+        assert!(!matches("Title == '20 April 2011'"));
+    }
+
+    #[test]
+    fn multiple_values() {
+        // the first value is used when evaluating...
+        assert_eq!(eval("Author = 'Jose E. Marchesi'"), Value::Int(0));
+        // ...but any value can make the expression match
+        assert!(matches("Author = 'Jose E. Marchesi'"));
+        assert!(!matches("Author = 'Richard M. Stallman'"));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(Expr::parse("Pages / 0").unwrap().eval(&book()).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+
+use {FieldType, Recfile, Record, RecordDescriptor};
+
+/// A `SortKey` names a field to sort records by
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SortKey {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl SortKey {
+    /// Parse a comma- or space-separated list of field names, where
+    /// a field name prefixed with `-` sorts in descending order, e.g.
+    /// `Title,-Year`
+    pub fn parse_list(s: &str) -> Vec<SortKey> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty())
+            .map(|f| match f.strip_prefix('-') {
+                Some(f) => SortKey { field: f.to_owned(), descending: true },
+                None => SortKey {
+                    field: f.strip_prefix('+').unwrap_or(f).to_owned(),
+                    descending: false,
+                },
+            })
+            .collect()
+    }
+
+    /// Compare two records by this key, using the field's declared
+    /// type if there is one. Records without the field sort last,
+    /// whichever order the key is in.
+    pub fn compare(
+        &self,
+        desc: Option<&RecordDescriptor>,
+        a: &Record,
+        b: &Record,
+    ) -> Ordering {
+        let typ = desc.and_then(|d| d.field_type(&self.field).ok())
+            .and_then(|t| t)
+            .unwrap_or(FieldType::Line);
+        match (a.get(&self.field), b.get(&self.field)) {
+            (Ok(x), Ok(y)) if self.descending => typ.compare(x, y).reverse(),
+            (Ok(x), Ok(y)) => typ.compare(x, y),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => Ordering::Equal,
+        }
+    }
+}
+
+impl Recfile {
+    /// The position of the record's type among the types in this
+    /// file, so that sorting keeps the records of each type together
+    fn type_index(&self, rec: &Record) -> Option<usize> {
+        rec.rec_type.as_ref()
+            .and_then(|t| self.descriptors.iter().position(|d| &d.rec_type == t))
+    }
+
+    /// Stably sort the records in this file by the given keys. Records
+    /// of different types are kept apart, and each record is compared
+    /// using the descriptor for its type.
+    pub fn sort_by_keys(&mut self, keys: &[SortKey]) {
+        self.sort_with(|_| keys)
+    }
+
+    /// Stably sort the records of each type by the fields named in
+    /// that type's `%sort`, leaving the others in file order
+    pub fn sort_by_descriptors(&mut self) {
+        let keys: Vec<(String, Vec<SortKey>)> = self.descriptors.iter()
+            .map(|d| (d.rec_type.clone(), SortKey::parse_list(&d.sort.join(" "))))
+            .collect();
+        self.sort_with(|desc| match desc {
+            Some(d) => keys.iter()
+                .find(|(t, _)| t == &d.rec_type)
+                .map(|(_, k)| k.as_slice())
+                .unwrap_or(&[]),
+            None => &[],
+        })
+    }
+
+    fn sort_with<'a, F>(&mut self, keys_for: F)
+        where F: Fn(Option<&RecordDescriptor>) -> &'a [SortKey]
+    {
+        let mut records = std::mem::take(&mut self.records);
+        records.sort_by(|a, b| {
+            let (ta, tb) = (self.type_index(a), self.type_index(b));
+            if ta != tb {
+                return ta.cmp(&tb);
+            }
+            let desc = self.descriptor_for(a);
+            compare_by_keys(keys_for(desc), desc, a, b)
+        });
+        self.records = records;
+    }
+}
+
+fn compare_by_keys(
+    keys: &[SortKey],
+    desc: Option<&RecordDescriptor>,
+    a: &Record,
+    b: &Record,
+) -> Ordering {
+    keys.iter()
+        .map(|k| k.compare(desc, a, b))
+        .find(|o| *o != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Stably sort a set of records which all share the descriptor
+/// `desc` by the given keys, e.g. a section of a file being read
+/// with a `RecordReader`
+pub fn sort_records(records: &mut [Record], desc: Option<&RecordDescriptor>, keys: &[SortKey]) {
+    records.sort_by(|a, b| compare_by_keys(keys, desc, a, b));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SortKey;
+    use Recfile;
+
+    fn titles(file: &Recfile) -> Vec<&str> {
+        file.iter().map(|r| r.get("Title").unwrap_or("")).collect()
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert_eq!(SortKey::parse_list("Title,-Year"), vec![
+            SortKey { field: "Title".to_owned(), descending: false },
+            SortKey { field: "Year".to_owned(), descending: true },
+        ]);
+    }
+
+    #[test]
+    fn typed_sort() {
+        let mut file = Recfile::parse(&b"%rec: Book
+%type: Year int
+
+Title: c
+Year: 10
+
+Title: a
+Year: 9
+
+Title: b
+Year: 10
+"[..]).unwrap();
+        file.sort_by_keys(&SortKey::parse_list("Year"));
+        assert_eq!(titles(&file), vec!["a", "c", "b"]);
+        file.sort_by_keys(&SortKey::parse_list("-Year,Title"));
+        assert_eq!(titles(&file), vec!["b", "c", "a"]);
+    }
+
+    #[test]
+    fn enum_sort_and_missing_fields() {
+        let mut file = Recfile::parse(&b"%rec: Book
+%type: Location enum loaned home unknown
+
+Title: a
+Location: unknown
+
+Title: b
+
+Title: c
+Location: loaned
+"[..]).unwrap();
+        file.sort_by_keys(&SortKey::parse_list("Location"));
+        assert_eq!(titles(&file), vec!["c", "a", "b"]);
+        file.sort_by_keys(&SortKey::parse_list("-Location"));
+        assert_eq!(titles(&file), vec!["a", "c", "b"]);
+    }
+
+    #[test]
+    fn descriptor_sort() {
+        let mut file = Recfile::parse(&b"%rec: A
+%sort: Title
+
+Title: z
+
+Title: y
+
+%rec: B
+
+Title: x
+
+Title: w
+"[..]).unwrap();
+        file.sort_by_descriptors();
+        assert_eq!(titles(&file), vec!["y", "z", "x", "w"]);
+    }
+}
@@ -2,17 +2,19 @@
 
 use std::{fs,io};
 
+use rrecutils::{Expr, RecError, Recfile, Selection, Violation};
+
 /// This can be changed to modify all the tool metadata all at once
-pub const VERSION: &'static str = "0.0";
-pub const AUTHOR: &'static str =
+pub const VERSION: &str = "0.0";
+pub const AUTHOR: &str =
     "Getty Ritter <rrecutils@infinitenegativeutility.com>";
 
 /// If this doesn't name a path, or if the path is `"-"`, then return
 /// a buffered reader from stdin; otherwise, attempt to open the file
 /// named by the path and return a buffered reader around it
-pub fn input_from_spec<'a>(
-    spec: Option<&'a str>
-) -> io::Result<io::BufReader<Box<io::Read>>> {
+pub fn input_from_spec(
+    spec: Option<&str>
+) -> io::Result<io::BufReader<Box<dyn io::Read>>> {
     match spec.unwrap_or("-") {
         "-" => Ok(io::BufReader::new(Box::new(io::stdin()))),
         path => {
@@ -25,12 +27,101 @@ pub fn input_from_spec<'a>(
 /// If this doesn't name a path, or if the path is `"-"`, then return
 /// a buffered writer to stdout; otherwise, attempt to open the file
 /// named by the path and return a writer around it
-pub fn output_from_spec<'a>(
-    spec: Option<&'a str>
-) -> io::Result<Box<io::Write>>
+pub fn output_from_spec(
+    spec: Option<&str>
+) -> io::Result<Box<dyn io::Write>>
 {
     match spec.unwrap_or("-") {
         "-" => Ok(Box::new(io::stdout())),
-        path => Ok(Box::new(fs::File::open(path)?)),
+        path => Ok(Box::new(fs::File::create(path)?)),
+    }
+}
+
+/// Read the whole of the file named by the path, or stdin if the
+/// path is `"-"`
+pub fn read_from_spec(spec: &str) -> io::Result<String> {
+    use std::io::Read;
+    let mut buf = String::new();
+    input_from_spec(Some(spec))?.read_to_string(&mut buf)?;
+    Ok(buf)
+}
+
+/// Replace the contents of the file named by the path, or write to
+/// stdout if the path is `"-"`. The new contents are written to a
+/// temporary file first so that a failed write can't leave the file
+/// half-written.
+pub fn write_in_place(spec: &str, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+    if spec == "-" {
+        return io::stdout().write_all(contents.as_bytes());
+    }
+    let tmp = format!("{}.rr-tmp", spec);
+    fs::write(&tmp, contents)?;
+    if let Ok(meta) = fs::metadata(spec) {
+        fs::set_permissions(&tmp, meta.permissions())?;
+    }
+    fs::rename(&tmp, spec)
+}
+
+/// Add the arguments for choosing records which are shared by the
+/// tools that modify a recfile in place
+pub fn selection_args<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.arg(clap::Arg::with_name("type")
+            .long("type")
+            .short("t")
+            .value_name("TYPE")
+            .takes_value(true)
+            .help("Only operate on records of this type"))
+
+        .arg(clap::Arg::with_name("expression")
+             .long("expression")
+             .short("e")
+             .value_name("EXPR")
+             .takes_value(true)
+             .help("Only operate on records matching this selection expression"))
+
+        .arg(clap::Arg::with_name("number")
+             .long("number")
+             .short("n")
+             .value_name("INDEXES")
+             .takes_value(true)
+             .help("Only operate on the records at these positions, e.g. 0,3-5"))
+
+        .arg(clap::Arg::with_name("quick")
+             .long("quick")
+             .short("q")
+             .value_name("STR")
+             .takes_value(true)
+             .help("Only operate on records with a value containing this string"))
+}
+
+/// Build a `Selection` out of the arguments added by `selection_args`
+pub fn selection_from_matches(matches: &clap::ArgMatches) -> Result<Selection, RecError> {
+    Ok(Selection {
+        rec_type: matches.value_of("type").map(|t| t.to_owned()),
+        expr: match matches.value_of("expression") {
+            Some(e) => Some(Expr::parse(e)?),
+            None => None,
+        },
+        indexes: match matches.value_of("number") {
+            Some(n) => Some(Selection::parse_indexes(n)?),
+            None => None,
+        },
+        quick: matches.value_of("quick").map(|q| q.to_owned()),
+    })
+}
+
+/// The integrity problems in `after` which weren't already in
+/// `before`, so that tools don't refuse to touch files which were
+/// already broken
+pub fn new_violations(before: &Recfile, after: &Recfile) -> Vec<Violation> {
+    let mut old = before.check_integrity();
+    let mut found = vec![];
+    for v in after.check_integrity() {
+        match old.iter().position(|o| o.rec_type == v.rec_type && o.message == v.message) {
+            Some(i) => { old.remove(i); }
+            None => found.push(v),
+        }
     }
+    found
 }
@@ -0,0 +1,80 @@
+extern crate clap;
+extern crate rrecutils;
+#[macro_use] extern crate failure;
+
+mod common;
+
+use failure::Error;
+use rrecutils::Document;
+
+fn rr_delete_args() -> clap::ArgMatches<'static> {
+    let app = clap::App::new("rr-del")
+        .version(common::VERSION)
+        .author(common::AUTHOR)
+        .about("Delete records from a recfile")
+
+        .arg(clap::Arg::with_name("file")
+             .value_name("FILE")
+             .help("The recfile to modify (or - to read stdin and write stdout)"))
+
+        .arg(clap::Arg::with_name("comment")
+             .long("comment")
+             .short("c")
+             .takes_value(false)
+             .help("Comment out the records instead of removing them"))
+
+        .arg(clap::Arg::with_name("force")
+             .long("force")
+             .takes_value(false)
+             .help("Delete the records even if that violates the record descriptor"));
+
+    common::selection_args(app).get_matches()
+}
+
+fn run() -> Result<bool, Error> {
+    let matches = rr_delete_args();
+    let path = matches.value_of("file").unwrap_or("-");
+    let selection = common::selection_from_matches(&matches)?;
+    if selection.is_empty() {
+        bail!("Refusing to delete every record: select some with -t, -e, -n or -q");
+    }
+
+    let source = common::read_from_spec(path)?;
+    let mut doc = Document::parse(&source)?;
+    let recfile = doc.to_recfile()?;
+
+    let comment = matches.is_present("comment");
+    // removing a record doesn't move the ones before it
+    for &i in recfile.select_indexes(&selection)?.iter().rev() {
+        if comment {
+            doc.comment_out_record(i);
+        } else {
+            doc.remove_record(i);
+        }
+    }
+
+    if !matches.is_present("force") {
+        let problems = common::new_violations(&recfile, &doc.to_recfile()?);
+        if !problems.is_empty() {
+            for v in problems {
+                eprintln!("{}: {}", path, v);
+            }
+            eprintln!("{}: not deleting (use --force to delete anyway)", path);
+            return Ok(false);
+        }
+    }
+
+    common::write_in_place(path, &doc.to_string())?;
+    Ok(true)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+extern crate clap;
+extern crate failure;
+extern crate rrecutils;
+
+mod common;
+
+fn rr_fix_args() -> clap::ArgMatches<'static> {
+    clap::App::new("rr-fix")
+        .version(common::VERSION)
+        .author(common::AUTHOR)
+        .about("Check the integrity of recfiles against their descriptors")
+
+        .arg(clap::Arg::with_name("input")
+             .value_name("FILE")
+             .multiple(true)
+             .help("The input recfiles (or - for stdin)"))
+
+        .get_matches()
+}
+
+/// Check a single file, printing each problem to stderr as
+/// `path:line[:column]: severity: message`, and return the number of
+/// errors found. Warnings about the input are printed, but don't
+/// count as errors.
+fn check_file(path: &str) -> Result<usize, failure::Error> {
+    let input = common::input_from_spec(Some(path))?;
+    let reader = rrecutils::RecordReader::new(input).with_file_name(path);
+    let (recfile, diagnostics) = rrecutils::Recfile::from_reader_recovering(reader);
+    for d in diagnostics.iter() {
+        // the reader was given the file name, so positions include it
+        match d.pos {
+            Some(_) => eprintln!("{}", d),
+            None => eprintln!("{}: {}", path, d),
+        }
+    }
+    let errors = diagnostics.iter()
+        .filter(|d| d.severity == rrecutils::Severity::Error)
+        .count();
+
+    let violations = recfile.check_integrity();
+    for v in violations.iter() {
+        match v.span {
+            Some(span) => eprintln!("{}:{}: error: {}", path, span.start, v),
+            None => eprintln!("{}: error: {}", path, v),
+        }
+    }
+
+    Ok(errors + violations.len())
+}
+
+fn run() -> Result<bool, failure::Error> {
+    let matches = rr_fix_args();
+
+    let paths: Vec<&str> = match matches.values_of("input") {
+        Some(vs) => vs.collect(),
+        None => vec!["-"],
+    };
+
+    let mut ok = true;
+    for path in paths {
+        match check_file(path) {
+            Ok(0) => (),
+            Ok(_) => ok = false,
+            Err(e) => {
+                eprintln!("{}: error: {}", path, e);
+                ok = false;
+            }
+        }
+    }
+
+    Ok(ok)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -101,7 +101,7 @@ fn create_output_files(
 
 
 fn render_to_single_file(
-    mut output: Box<std::io::Write>,
+    mut output: Box<dyn std::io::Write>,
     joiner: Option<&str>,
     recfile: rrecutils::Recfile,
     template: String,
@@ -111,8 +111,8 @@ fn render_to_single_file(
         if first {
             first = false;
         } else if let Some(j) = joiner {
-            output.write(j.as_bytes())?;
-            output.write(&['\n' as u8])?;
+            output.write_all(j.as_bytes())?;
+            output.write_all(b"\n")?;
         }
         R { rec: r }.render(&template, &mut output.as_mut())
             .map_err(|e| format_err!("Rustache error: {:?}", e))?;
@@ -0,0 +1,126 @@
+extern crate clap;
+extern crate rrecutils;
+#[macro_use] extern crate failure;
+
+mod common;
+
+use failure::Error;
+use rrecutils::{Document, Recfile, Record};
+use rrecutils::reader::valid_field_name;
+
+fn rr_insert_args() -> clap::ArgMatches<'static> {
+    clap::App::new("rr-ins")
+        .version(common::VERSION)
+        .author(common::AUTHOR)
+        .about("Insert records into a recfile")
+
+        .arg(clap::Arg::with_name("file")
+             .value_name("FILE")
+             .help("The recfile to modify (or - to read stdin and write stdout)"))
+
+        .arg(clap::Arg::with_name("type")
+             .long("type")
+             .short("t")
+             .value_name("TYPE")
+             .takes_value(true)
+             .help("The type of the new record"))
+
+        .arg(clap::Arg::with_name("field")
+             .long("field")
+             .short("f")
+             .value_name("NAME")
+             .multiple(true)
+             .number_of_values(1)
+             .help("The name of a field in the new record"))
+
+        .arg(clap::Arg::with_name("value")
+             .long("value")
+             .short("v")
+             .value_name("VALUE")
+             .multiple(true)
+             .number_of_values(1)
+             .allow_hyphen_values(true)
+             .help("The value of the preceding field"))
+
+        .arg(clap::Arg::with_name("force")
+             .long("force")
+             .takes_value(false)
+             .help("Insert the record even if it violates the record descriptor"))
+
+        .get_matches()
+}
+
+/// Collect the new records either from `-f`/`-v` pairs or, if there
+/// are none, from stdin
+fn new_records(matches: &clap::ArgMatches, rec_type: Option<String>) -> Result<Vec<Record>, Error> {
+    let names: Vec<&str> = matches.values_of("field").map(|v| v.collect()).unwrap_or_default();
+    let values: Vec<&str> = matches.values_of("value").map(|v| v.collect()).unwrap_or_default();
+    if names.len() != values.len() {
+        bail!("Every -f needs a matching -v");
+    }
+    if let Some(name) = names.iter().find(|n| !valid_field_name(n)) {
+        bail!("Invalid field name: {}", name);
+    }
+
+    if !names.is_empty() {
+        return Ok(vec![Record::new(rec_type.as_deref(), names.into_iter().zip(values))]);
+    }
+
+    if matches.value_of("file").unwrap_or("-") == "-" {
+        bail!("No fields given with -f/-v, and stdin is already the input file");
+    }
+    let input = Recfile::parse(common::input_from_spec(None)?)?;
+    Ok(input.records.into_iter()
+       .map(|r| Record { splits: r.splits, ..Record::new(rec_type.as_deref(), r.fields) })
+       .collect())
+}
+
+fn run() -> Result<bool, Error> {
+    let matches = rr_insert_args();
+    let path = matches.value_of("file").unwrap_or("-");
+    let rec_type = matches.value_of("type").map(|t| t.to_owned());
+    // a type is named like a field, but can't be a special field
+    if let Some(ref t) = rec_type {
+        if !valid_field_name(t) || t.starts_with('%') {
+            bail!("Invalid record type: {}", t);
+        }
+    }
+
+    let source = common::read_from_spec(path)?;
+    let mut doc = Document::parse(&source)?;
+    let before = doc.to_recfile()?;
+    let mut recfile = doc.to_recfile()?;
+    let records = new_records(&matches, rec_type)?;
+
+    for mut rec in records {
+        recfile.fill_auto(&mut rec);
+        doc.insert_record(&rec);
+        // later records should see this one when picking %auto values
+        recfile.records.push(rec);
+    }
+
+    if !matches.is_present("force") {
+        let problems = common::new_violations(&before, &doc.to_recfile()?);
+        if !problems.is_empty() {
+            for v in problems {
+                eprintln!("{}: {}", path, v);
+            }
+            eprintln!("{}: not inserting (use --force to insert anyway)", path);
+            return Ok(false);
+        }
+    }
+
+    common::write_in_place(path, &doc.to_string())?;
+    Ok(true)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -5,6 +5,8 @@ extern crate failure;
 mod common;
 
 use failure::Error;
+use rrecutils::{Entry, Expr, FieldExpr, Recfile, Record, RecordDescriptor, RecordReader, SortKey};
+use rrecutils::sort::sort_records;
 
 fn rr_select_args() -> clap::ArgMatches<'static> {
     clap::App::new("rr-sel")
@@ -30,48 +32,329 @@ fn rr_select_args() -> clap::ArgMatches<'static> {
              .required(false)
              .takes_value(true))
 
+        .arg(clap::Arg::with_name("expression")
+             .long("expression")
+             .short("e")
+             .value_name("EXPR")
+             .required(false)
+             .takes_value(true)
+             .help("Only print records matching this selection expression"))
+
+        .arg(clap::Arg::with_name("print")
+             .long("print")
+             .short("p")
+             .value_name("FIELDS")
+             .required(false)
+             .takes_value(true)
+             .conflicts_with_all(&["print-values", "print-row"])
+             .help("Only print the fields selected by this field expression"))
+
+        .arg(clap::Arg::with_name("print-values")
+             .long("print-values")
+             .short("P")
+             .value_name("FIELDS")
+             .required(false)
+             .takes_value(true)
+             .conflicts_with("print-row")
+             .help("Print the values of the selected fields, one per line"))
+
+        .arg(clap::Arg::with_name("print-row")
+             .long("print-row")
+             .short("R")
+             .value_name("FIELDS")
+             .required(false)
+             .takes_value(true)
+             .help("Print the values of the selected fields on one line per record"))
+
         .arg(clap::Arg::with_name("include-descriptors")
              .long("include-descriptors")
              .short("d")
              .required(false)
-             .takes_value(false))
+             .takes_value(false)
+             .help("Print the record descriptor before the records of each type"))
 
         .arg(clap::Arg::with_name("collapse")
              .long("collapse")
              .short("C")
              .required(false)
-             .takes_value(false))
+             .takes_value(false)
+             .help("Do not separate records with blank lines"))
 
         .arg(clap::Arg::with_name("sort")
              .long("sort")
              .short("S")
+             .value_name("FIELDS")
              .required(false)
-             .takes_value(true))
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .help("Sort by these comma-separated fields (prefix with - to reverse)"))
 
         .arg(clap::Arg::with_name("group-by")
              .long("group-by")
              .short("G")
+             .value_name("FIELDS")
              .required(false)
-             .takes_value(true))
+             .takes_value(true)
+             .help("Merge records with the same values for these comma-separated fields"))
 
         .get_matches()
 }
 
+/// The ways that `-p`, `-P` and `-R` can restrict what gets printed
+enum Projection {
+    All,
+    Fields(FieldExpr),
+    Values(FieldExpr),
+    Row(FieldExpr),
+}
+
+impl Projection {
+    fn from_matches(matches: &clap::ArgMatches) -> Result<Projection, Error> {
+        Ok(if let Some(p) = matches.value_of("print") {
+            Projection::Fields(FieldExpr::parse(p)?)
+        } else if let Some(p) = matches.value_of("print-values") {
+            Projection::Values(FieldExpr::parse(p)?)
+        } else if let Some(p) = matches.value_of("print-row") {
+            Projection::Row(FieldExpr::parse(p)?)
+        } else {
+            Projection::All
+        })
+    }
+
+    fn fex(&self) -> Option<&FieldExpr> {
+        match *self {
+            Projection::All => None,
+            Projection::Fields(ref fex)
+                | Projection::Values(ref fex)
+                | Projection::Row(ref fex) => Some(fex),
+        }
+    }
+
+    /// Write a record, returning whether a separating blank line is
+    /// still owed afterwards
+    fn write(
+        &self,
+        output: &mut dyn std::io::Write,
+        r: &Record,
+        collapse: bool,
+    ) -> Result<bool, Error> {
+        match self.fex() {
+            Some(fex) => self.write_projected(output, &fex.project(r), collapse),
+            None => self.write_projected(output, r, collapse),
+        }
+    }
+
+    /// Write a record which has already had the field expression
+    /// applied to it
+    fn write_projected(
+        &self,
+        output: &mut dyn std::io::Write,
+        r: &Record,
+        collapse: bool,
+    ) -> Result<bool, Error> {
+        let values = r.fields.iter().map(|(_, v)| v.as_str());
+
+        match *self {
+            Projection::All | Projection::Fields(_) => write_record(output, r, collapse),
+            Projection::Values(_) => {
+                for v in values {
+                    writeln!(output, "{}", v)?;
+                }
+                if !collapse {
+                    writeln!(output)?;
+                }
+                Ok(collapse)
+            }
+            Projection::Row(_) => {
+                writeln!(output, "{}", values.collect::<Vec<_>>().join(" "))?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+fn write_record(
+    mut output: &mut dyn std::io::Write,
+    r: &Record,
+    collapse: bool,
+) -> Result<bool, Error> {
+    if r.fields.is_empty() {
+        Ok(false)
+    } else if collapse {
+        r.write_fields(&mut output)?;
+        Ok(true)
+    } else {
+        r.write(&mut output)?;
+        Ok(false)
+    }
+}
+
+/// Writes out the selected records, along with their descriptors if
+/// they were asked for
+struct Output {
+    output: Box<dyn std::io::Write>,
+    projection: Projection,
+    include_descriptors: bool,
+    collapse: bool,
+    /// The types whose descriptors have been written, so that each
+    /// descriptor is only written once
+    described: Vec<String>,
+    /// Whether collapsed records have been written since the last
+    /// blank line
+    pending: bool,
+}
+
+impl Output {
+    fn record(
+        &mut self,
+        desc: Option<&RecordDescriptor>,
+        r: &Record,
+    ) -> Result<(), Error> {
+        if let Some(d) = desc.filter(|d| self.include_descriptors && !self.described.contains(&d.rec_type)) {
+            if self.pending {
+                writeln!(self.output)?;
+                self.pending = false;
+            }
+            d.write(&mut self.output)?;
+            self.described.push(d.rec_type.clone());
+        }
+
+        self.pending |= self.projection.write(&mut self.output, r, self.collapse)?;
+        Ok(())
+    }
+}
+
+/// Returns `true` if a record passes the `-t` and `-e` filters
+fn selected(matches: &clap::ArgMatches, expr: Option<&Expr>, r: &Record) -> Result<bool, Error> {
+    if let Some(typ) = matches.value_of("type") {
+        if r.rec_type.as_deref() != Some(typ) {
+            return Ok(false);
+        }
+    }
+    Ok(match expr {
+        Some(e) => e.matches(r)?,
+        None => true,
+    })
+}
+
+/// Write the selected records as they are read, which is possible
+/// when nothing needs to see the whole file at once. The records of
+/// a type with a `%sort` are still held back until the end of their
+/// section so that they can be sorted.
+fn stream(
+    matches: &clap::ArgMatches,
+    expr: Option<&Expr>,
+    mut reader: RecordReader<impl std::io::BufRead>,
+    out: &mut Output,
+) -> Result<(), Error> {
+    let mut desc: Option<RecordDescriptor> = None;
+    let mut section: Vec<Record> = vec![];
+
+    let flush = |desc: &Option<RecordDescriptor>, section: &mut Vec<Record>, out: &mut Output| {
+        if let Some(ref d) = *desc {
+            sort_records(section, Some(d), &SortKey::parse_list(&d.sort.join(" ")));
+        }
+        for r in section.drain(..) {
+            out.record(desc.as_ref(), &r)?;
+        }
+        Ok(())
+    };
+
+    while let Some(entry) = reader.next_entry() {
+        match entry? {
+            Entry::Descriptor(d) => {
+                flush(&desc, &mut section, out)?;
+                desc = Some(*d);
+            }
+            Entry::Record(r) => {
+                if !selected(matches, expr, &r)? {
+                    continue;
+                }
+                match desc {
+                    Some(ref d) if !d.sort.is_empty() => section.push(r),
+                    _ => out.record(desc.as_ref(), &r)?,
+                }
+            }
+        }
+    }
+    flush(&desc, &mut section, out)
+}
+
 fn run() -> Result<(), Error> {
     let matches = rr_select_args();
 
     let input = common::input_from_spec(
         matches.value_of("input"))?;
-    let mut output = common::output_from_spec(
+    let output = common::output_from_spec(
         matches.value_of("output"))?;
 
-    let mut records = rrecutils::Recfile::parse(input)?;
+    let expr = match matches.value_of("expression") {
+        Some(e) => Some(Expr::parse(e)?),
+        None => None,
+    };
+    let projection = Projection::from_matches(&matches)?;
+    let aggregates = projection.fex().is_some_and(|f| f.has_aggregates());
+    let grouped = matches.is_present("group-by");
+
+    let mut out = Output {
+        output,
+        projection,
+        include_descriptors: matches.is_present("include-descriptors"),
+        collapse: matches.is_present("collapse"),
+        described: vec![],
+        pending: false,
+    };
+
+    // a type can have several sections in a file, so its records are
+    // only kept together under one descriptor if the whole file is read
+    let streamable = !matches.is_present("include-descriptors");
+    if streamable && !matches.is_present("sort") && !grouped && !aggregates {
+        return stream(&matches, expr.as_ref(), RecordReader::new(input), &mut out);
+    }
+
+    let mut records = Recfile::parse(input)?;
 
     if let Some(typ) = matches.value_of("type") {
         records.filter_by_type(typ);
     }
 
-    records.write(&mut output)?;
+    if let Some(ref e) = expr {
+        records.filter_by_expr(e)?;
+    }
+
+    let sort_keys = matches.value_of("sort").map(SortKey::parse_list);
+    match sort_keys {
+        Some(ref keys) => records.sort_by_keys(keys),
+        None => records.sort_by_descriptors(),
+    }
+
+    if let Some(fields) = matches.value_of("group-by") {
+        let fields: Vec<String> = fields.split(',')
+            .map(|f| f.trim().to_owned())
+            .filter(|f| !f.is_empty())
+            .collect();
+        records.group_by(&fields);
+        // grouping orders the groups by the grouping fields, so put
+        // them back in the order asked for; the records within each
+        // group are already in that order
+        if let Some(ref keys) = sort_keys {
+            records.sort_by_keys(keys);
+        }
+    }
+
+    // without --group-by, aggregate functions summarize all of the
+    // selected records in a single record
+    if let Some(fex) = out.projection.fex() {
+        if fex.has_aggregates() && !grouped {
+            let summary = fex.aggregate(records.iter());
+            out.projection.write_projected(&mut out.output, &summary, out.collapse)?;
+            return Ok(());
+        }
+    }
+
+    for r in records.iter() {
+        out.record(records.descriptor_for(r), r)?;
+    }
 
     Ok(())
 }
@@ -79,6 +362,9 @@ fn run() -> Result<(), Error> {
 fn main() {
     match run() {
         Ok(()) => (),
-        Err(e) => println!("{}", e),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
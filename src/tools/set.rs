@@ -0,0 +1,176 @@
+extern crate clap;
+extern crate rrecutils;
+#[macro_use] extern crate failure;
+
+mod common;
+
+use failure::Error;
+use rrecutils::Document;
+use rrecutils::edit::FieldOp;
+use rrecutils::reader::valid_field_name;
+
+fn rr_set_args() -> clap::ArgMatches<'static> {
+    let app = clap::App::new("rr-set")
+        .version(common::VERSION)
+        .author(common::AUTHOR)
+        .about("Change the fields of records in a recfile")
+
+        .arg(clap::Arg::with_name("file")
+             .value_name("FILE")
+             .help("The recfile to modify (or - to read stdin and write stdout)"))
+
+        .arg(clap::Arg::with_name("field")
+             .long("field")
+             .short("f")
+             .value_name("NAME")
+             .takes_value(true)
+             .required(true)
+             .help("The field to change"))
+
+        .arg(clap::Arg::with_name("set")
+             .long("set")
+             .short("s")
+             .value_name("VALUE")
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .help("Set the value of the field"))
+
+        .arg(clap::Arg::with_name("set-add")
+             .long("set-add")
+             .short("S")
+             .value_name("VALUE")
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .help("Set the value of the field, adding it if it is missing"))
+
+        .arg(clap::Arg::with_name("add")
+             .long("add")
+             .short("a")
+             .value_name("VALUE")
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .help("Add a new instance of the field"))
+
+        .arg(clap::Arg::with_name("rename")
+             .long("rename")
+             .short("r")
+             .value_name("NAME")
+             .takes_value(true)
+             .help("Rename the field"))
+
+        .arg(clap::Arg::with_name("delete")
+             .long("delete")
+             .short("d")
+             .takes_value(false)
+             .help("Remove the field"))
+
+        .arg(clap::Arg::with_name("comment")
+             .long("comment")
+             .short("c")
+             .takes_value(false)
+             .help("Comment out the field"))
+
+        .group(clap::ArgGroup::with_name("action")
+               .args(&["set", "set-add", "add", "rename", "delete", "comment"])
+               .required(true))
+
+        .arg(clap::Arg::with_name("descriptors")
+             .long("descriptors")
+             .takes_value(false)
+             .help("Also rename or remove the field in the record descriptor"))
+
+        .arg(clap::Arg::with_name("force")
+             .long("force")
+             .takes_value(false)
+             .help("Change the records even if that violates the record descriptor"));
+
+    common::selection_args(app).get_matches()
+}
+
+fn field_op(matches: &clap::ArgMatches) -> Result<FieldOp, Error> {
+    let value = |name| matches.value_of(name).unwrap_or("").to_owned();
+    Ok(if matches.is_present("set") {
+        FieldOp::Set(value("set"))
+    } else if matches.is_present("set-add") {
+        FieldOp::SetOrAdd(value("set-add"))
+    } else if matches.is_present("add") {
+        FieldOp::Add(value("add"))
+    } else if matches.is_present("rename") {
+        let name = value("rename");
+        if !valid_field_name(&name) {
+            bail!("Invalid field name: {}", name);
+        }
+        FieldOp::Rename(name)
+    } else if matches.is_present("delete") {
+        FieldOp::Delete
+    } else {
+        FieldOp::Comment
+    })
+}
+
+fn run() -> Result<bool, Error> {
+    let matches = rr_set_args();
+    let path = matches.value_of("file").unwrap_or("-");
+    let field = matches.value_of("field").unwrap_or("");
+    if !valid_field_name(field) {
+        bail!("Invalid field name: {}", field);
+    }
+    let op = field_op(&matches)?;
+    let selection = common::selection_from_matches(&matches)?;
+
+    let source = common::read_from_spec(path)?;
+    let mut doc = Document::parse(&source)?;
+    let recfile = doc.to_recfile()?;
+    let selected = recfile.select_indexes(&selection)?;
+
+    let records = doc.records_mut().filter(|r| !r.is_descriptor()).enumerate();
+    for (i, rec) in records {
+        if selected.binary_search(&i).is_ok() {
+            rec.edit_field(field, &op);
+        }
+    }
+    // a record whose fields were all commented out or deleted is no
+    // longer a record, and mustn't shift the indexes of the others
+    doc.empty_records_to_trivia();
+
+    if matches.is_present("descriptors") {
+        let mut types: Vec<&str> = selection.rec_type.iter().map(|t| t.as_str()).collect();
+        for &i in selected.iter() {
+            if let Some(ref t) = recfile.records[i].rec_type {
+                if !types.contains(&t.as_str()) {
+                    types.push(t);
+                }
+            }
+        }
+        for t in types {
+            if let Some(desc) = doc.descriptor_mut(t) {
+                desc.edit_descriptor_field(field, &op);
+            }
+        }
+    }
+
+    if !matches.is_present("force") {
+        let problems = common::new_violations(&recfile, &doc.to_recfile()?);
+        if !problems.is_empty() {
+            for v in problems {
+                eprintln!("{}: {}", path, v);
+            }
+            eprintln!("{}: not changing (use --force to change anyway)", path);
+            return Ok(false);
+        }
+    }
+
+    common::write_in_place(path, &doc.to_string())?;
+    Ok(true)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
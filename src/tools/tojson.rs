@@ -51,20 +51,31 @@ fn run() -> Result<(), failure::Error> {
     let mut output = common::output_from_spec(
         matches.value_of("output"))?;
 
-    let json = Value::Array(
-        rrecutils::Recfile::parse(input)?
-            .records
-            .iter()
-            .map(|x| record_to_json(x))
-            .collect());
-
-    let serialized = if matches.is_present("pretty") {
-        serde_json::to_string_pretty(&json)?
-    } else {
-        json.to_string()
-    };
-
-    writeln!(output, "{}", serialized)?;
+    // records are written as they are read, so this produces the
+    // same text as serializing the whole array at once. An error
+    // partway through leaves the array unfinished, and is reported
+    // by `main`.
+    let pretty = matches.is_present("pretty");
+    write!(output, "[")?;
+    let mut first = true;
+    for rec in rrecutils::RecordReader::new(input) {
+        let json = record_to_json(&rec?);
+        if !first {
+            write!(output, ",")?;
+        }
+        if pretty {
+            for ln in serde_json::to_string_pretty(&json)?.lines() {
+                write!(output, "\n  {}", ln)?;
+            }
+        } else {
+            write!(output, "{}", json)?;
+        }
+        first = false;
+    }
+    if pretty && !first {
+        writeln!(output)?;
+    }
+    writeln!(output, "]")?;
 
     Ok(())
 }
@@ -72,6 +83,9 @@ fn run() -> Result<(), failure::Error> {
 fn main() {
     match run() {
         Ok(()) => (),
-        Err(e) => println!("{}", e),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
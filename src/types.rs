@@ -0,0 +1,425 @@
+use std::cmp::Ordering;
+
+use regex::Regex;
+
+use reader::valid_field_name;
+use {date, Position, RecError, Record, RecordDescriptor};
+
+/// A compiled regular expression from a `regexp` type. This exists
+/// so that `FieldType` can be compared for equality.
+#[derive(Debug, Clone)]
+pub struct TypeRegex(pub Regex);
+
+impl PartialEq for TypeRegex {
+    fn eq(&self, other: &TypeRegex) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for TypeRegex {}
+
+/// A `FieldType` is the type of a field as given by a `%type` or
+/// `%typedef` line in a record descriptor
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum FieldType {
+    Int,
+    Real,
+    Bool,
+    /// An integer between the two bounds, inclusive
+    Range(i64, i64),
+    Enum(Vec<String>),
+    Regexp(TypeRegex),
+    Date,
+    Email,
+    Uuid,
+    /// A string without any newlines
+    Line,
+    /// A string of at most this many characters
+    Size(usize),
+    /// A valid field name
+    Field,
+    /// A foreign key into records of the named type
+    Rec(String),
+    /// A reference to a type declared with `%typedef`
+    Named(String),
+}
+
+fn bad_type(desc: &str) -> RecError {
+    RecError::InvalidDescriptor {
+        message: format!("bad type description: {}", desc),
+        pos: Position::default(),
+    }
+}
+
+/// Parse a recutils integer, which may be written in decimal or in
+/// hexadecimal with a `0x` prefix
+pub fn parse_int(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let n = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+            i64::from_str_radix(hex, 16).ok()?,
+        Some(_) => return None,
+        None if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) =>
+            s.parse().ok()?,
+        None => return None,
+    };
+    Some(if neg { -n } else { n })
+}
+
+/// Parse a recutils real number
+pub fn parse_real(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let valid = s.chars().any(|c| c.is_ascii_digit())
+        && s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+    if valid { s.parse().ok() } else { None }
+}
+
+/// Parse a recutils boolean, which may be any of `yes`/`no`,
+/// `true`/`false` or `1`/`0`
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim() {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn is_email(s: &str) -> bool {
+    let mut parts = s.split('@');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(user), Some(host), None) =>
+            !user.is_empty() && !host.is_empty()
+                && !s.chars().any(char::is_whitespace),
+        _ => false,
+    }
+}
+
+fn is_uuid(s: &str) -> bool {
+    let groups: Vec<&str> = s.split('-').collect();
+    groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12].iter().cloned())
+        && groups.iter().all(|g| g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Remove the parenthesized comments that are allowed in an `enum`
+/// type description
+fn strip_comments(s: &str) -> String {
+    let mut depth = 0;
+    s.chars().filter(|&c| match c {
+        '(' => { depth += 1; false },
+        ')' if depth > 0 => { depth -= 1; false },
+        _ => depth == 0,
+    }).collect()
+}
+
+fn range_bound(s: &str, default: i64) -> Option<i64> {
+    match s {
+        "MIN" => Some(i64::MIN),
+        "MAX" => Some(i64::MAX),
+        _ if s.is_empty() => Some(default),
+        _ => parse_int(s),
+    }
+}
+
+impl FieldType {
+    /// Parse a type description, e.g. `int`, `range 0 10` or
+    /// `enum loaned home unknown`. A single word which isn't a
+    /// built-in type is taken to be the name of a `%typedef`.
+    pub fn parse(desc: &str) -> Result<FieldType, RecError> {
+        let desc = desc.trim();
+        let (kw, rest) = match desc.find(char::is_whitespace) {
+            Some(pos) => (&desc[..pos], desc[pos..].trim()),
+            None => (desc, ""),
+        };
+
+        let typ = match kw {
+            "int" => FieldType::Int,
+            "real" => FieldType::Real,
+            "bool" => FieldType::Bool,
+            "date" => FieldType::Date,
+            "email" => FieldType::Email,
+            "uuid" => FieldType::Uuid,
+            "line" => FieldType::Line,
+            "field" => FieldType::Field,
+            "range" => {
+                let bounds: Vec<&str> = rest.split_whitespace().collect();
+                let (lo, hi) = match bounds.len() {
+                    1 => (Some(0), range_bound(bounds[0], i64::MAX)),
+                    2 => (range_bound(bounds[0], 0), range_bound(bounds[1], i64::MAX)),
+                    _ => (None, None),
+                };
+                match (lo, hi) {
+                    (Some(lo), Some(hi)) if lo <= hi => FieldType::Range(lo, hi),
+                    _ => return Err(bad_type(desc)),
+                }
+            }
+            "size" => FieldType::Size(
+                parse_int(rest)
+                    .filter(|&n| n >= 0)
+                    .ok_or_else(|| bad_type(desc))? as usize),
+            "enum" => {
+                let vals: Vec<String> = strip_comments(rest)
+                    .split_whitespace()
+                    .map(|s| s.to_owned())
+                    .collect();
+                if vals.is_empty() {
+                    return Err(bad_type(desc));
+                }
+                FieldType::Enum(vals)
+            }
+            "regexp" => {
+                let delim = rest.chars().next().ok_or_else(|| bad_type(desc))?;
+                let body = &rest[delim.len_utf8()..];
+                if !body.ends_with(delim) {
+                    return Err(bad_type(desc));
+                }
+                let re = Regex::new(&body[..body.len() - delim.len_utf8()])
+                    .map_err(|_| bad_type(desc))?;
+                FieldType::Regexp(TypeRegex(re))
+            }
+            "rec" if !rest.is_empty() => FieldType::Rec(rest.to_owned()),
+            _ if rest.is_empty() && valid_field_name(kw) => FieldType::Named(kw.to_owned()),
+            _ => return Err(bad_type(desc)),
+        };
+
+        Ok(typ)
+    }
+
+    /// Returns `true` if the value is a valid member of this type.
+    /// `Named` types must be resolved with `RecordDescriptor::field_type`
+    /// first, and accept nothing on their own.
+    pub fn accepts(&self, value: &str) -> bool {
+        match *self {
+            FieldType::Int => parse_int(value).is_some(),
+            FieldType::Real => parse_real(value).is_some(),
+            FieldType::Bool => parse_bool(value).is_some(),
+            FieldType::Range(lo, hi) => match parse_int(value) {
+                Some(n) => lo <= n && n <= hi,
+                None => false,
+            },
+            FieldType::Enum(ref vals) => vals.iter().any(|v| v == value.trim()),
+            FieldType::Regexp(ref re) => re.0.is_match(value),
+            FieldType::Date => date::parse(value).is_some(),
+            FieldType::Email => is_email(value.trim()),
+            FieldType::Uuid => is_uuid(value.trim()),
+            FieldType::Line => !value.contains('\n'),
+            FieldType::Size(n) => value.chars().count() <= n,
+            FieldType::Field => valid_field_name(value.trim()),
+            FieldType::Rec(_) => !value.trim().is_empty(),
+            FieldType::Named(_) => false,
+        }
+    }
+
+    /// Compare two values of this type: numerically for numeric
+    /// types, chronologically for dates, by declaration order for
+    /// enums and lexically for everything else. Values which aren't
+    /// valid members of the type sort after the ones which are.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        fn by<T, F>(a: &str, b: &str, f: F) -> Ordering
+            where F: Fn(&str) -> Option<T>, T: PartialOrd
+        {
+            match (f(a), f(b)) {
+                (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => a.cmp(b),
+            }
+        }
+
+        match *self {
+            FieldType::Int | FieldType::Range(..) => by(a, b, parse_int),
+            FieldType::Real => by(a, b, parse_real),
+            FieldType::Bool => by(a, b, parse_bool),
+            FieldType::Date => by(a, b, date::parse),
+            FieldType::Enum(ref vals) =>
+                by(a, b, |v| vals.iter().position(|e| e == v.trim())),
+            _ => a.cmp(b),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            FieldType::Int => write!(f, "int"),
+            FieldType::Real => write!(f, "real"),
+            FieldType::Bool => write!(f, "bool"),
+            FieldType::Range(lo, hi) => write!(f, "range {} {}", lo, hi),
+            FieldType::Enum(ref vals) => write!(f, "enum {}", vals.join(" ")),
+            FieldType::Regexp(ref re) => write!(f, "regexp /{}/", re.0.as_str()),
+            FieldType::Date => write!(f, "date"),
+            FieldType::Email => write!(f, "email"),
+            FieldType::Uuid => write!(f, "uuid"),
+            FieldType::Line => write!(f, "line"),
+            FieldType::Size(n) => write!(f, "size {}", n),
+            FieldType::Field => write!(f, "field"),
+            FieldType::Rec(ref t) => write!(f, "rec {}", t),
+            FieldType::Named(ref n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl RecordDescriptor {
+    /// Return the (unparsed) type description declared by
+    /// `%typedef` for the named type, if any
+    pub fn typedef(&self, name: &str) -> Option<&str> {
+        self.typedefs.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, t)| t.as_ref())
+    }
+
+    /// Resolve a `Named` type through this descriptor's `%typedef`s,
+    /// returning any other type unchanged
+    pub fn resolve(&self, typ: FieldType) -> Result<FieldType, RecError> {
+        let mut typ = typ;
+        // a chain of typedefs longer than the number of typedefs
+        // must contain a cycle
+        for _ in 0..=self.typedefs.len() {
+            typ = match typ {
+                FieldType::Named(name) => match self.typedef(&name) {
+                    Some(desc) => FieldType::parse(desc).map_err(|e| e.in_descriptor(self.span))?,
+                    None => return Err(RecError::InvalidDescriptor {
+                        message: format!("unknown type: {}", name),
+                        pos: Position::default(),
+                    }.in_descriptor(self.span)),
+                },
+                t => return Ok(t),
+            };
+        }
+        Err(RecError::InvalidDescriptor {
+            message: "cyclic %typedef".to_owned(),
+            pos: Position::default(),
+        }.in_descriptor(self.span))
+    }
+
+    /// Return the fully-resolved type of a field, or `None` if the
+    /// field has no declared type
+    pub fn field_type(&self, field: &str) -> Result<Option<FieldType>, RecError> {
+        match self.type_of(field) {
+            Some(desc) => {
+                let typ = FieldType::parse(desc).map_err(|e| e.in_descriptor(self.span))?;
+                Ok(Some(self.resolve(typ)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Check a single value against the declared type of a field.
+    /// Fields without a declared type accept any value.
+    pub fn check_field(&self, field: &str, value: &str) -> Result<(), RecError> {
+        match self.field_type(field)? {
+            Some(ref typ) if !typ.accepts(value) => Err(RecError::TypeMismatch {
+                name: field.to_owned(),
+                value: value.to_owned(),
+                typ: typ.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check every field of a record against its declared type,
+    /// returning all of the errors found
+    pub fn type_errors(&self, rec: &Record) -> Vec<RecError> {
+        rec.fields.iter()
+            .filter_map(|(k, v)| self.check_field(k, v).err())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FieldType;
+    use {RecError, Record, RecordDescriptor};
+
+    #[test]
+    fn parse_types() {
+        assert_eq!(FieldType::parse("int"), Ok(FieldType::Int));
+        assert_eq!(FieldType::parse("range 10"), Ok(FieldType::Range(0, 10)));
+        assert_eq!(FieldType::parse("range -1 MAX"), Ok(FieldType::Range(-1, i64::MAX)));
+        assert_eq!(
+            FieldType::parse("enum loaned (out) home unknown"),
+            Ok(FieldType::Enum(vec![
+                "loaned".to_owned(), "home".to_owned(), "unknown".to_owned(),
+            ])));
+        assert_eq!(FieldType::parse("Id_t"), Ok(FieldType::Named("Id_t".to_owned())));
+        assert!(FieldType::parse("range 5 1").is_err());
+        assert!(FieldType::parse("regexp /[a-z/").is_err());
+        assert!(FieldType::parse("enum").is_err());
+    }
+
+    #[test]
+    fn accepts_values() {
+        assert!(FieldType::Int.accepts("-12"));
+        assert!(FieldType::Int.accepts("0x1F"));
+        assert!(!FieldType::Int.accepts("1.5"));
+        assert!(FieldType::Real.accepts("1.5e3"));
+        assert!(!FieldType::Real.accepts("inf"));
+        assert!(FieldType::Bool.accepts("yes"));
+        assert!(!FieldType::Bool.accepts("maybe"));
+        assert!(FieldType::Range(1, 3).accepts("3"));
+        assert!(!FieldType::Range(1, 3).accepts("4"));
+        assert!(FieldType::Date.accepts("20 April 2011"));
+        assert!(FieldType::Email.accepts("jemarch@gnu.org"));
+        assert!(!FieldType::Email.accepts("jemarch"));
+        assert!(FieldType::Uuid.accepts("f81d4fae-7dec-11d0-a765-00a0c91e6bf6"));
+        assert!(!FieldType::Line.accepts("two\nlines"));
+        assert!(FieldType::Size(3).accepts("abc"));
+        assert!(!FieldType::Size(3).accepts("abcd"));
+        assert!(FieldType::Field.accepts("Title"));
+        assert!(!FieldType::Field.accepts("2Title"));
+        assert!(FieldType::parse("regexp /^[0-9]+$/").unwrap().accepts("123"));
+    }
+
+    #[test]
+    fn compare_values() {
+        use std::cmp::Ordering::*;
+        assert_eq!(FieldType::Int.compare("9", "10"), Less);
+        assert_eq!(FieldType::Line.compare("9", "10"), Greater);
+        assert_eq!(FieldType::Int.compare("x", "10"), Greater);
+        assert_eq!(FieldType::Real.compare("1.5", "1.25"), Greater);
+        assert_eq!(FieldType::Date.compare("20 April 2011", "2011-01-01"), Greater);
+        let e = FieldType::parse("enum loaned home unknown").unwrap();
+        assert_eq!(e.compare("unknown", "home"), Greater);
+        assert_eq!(e.compare("loaned", "home"), Less);
+    }
+
+    #[test]
+    fn typedefs_resolve() {
+        let d = RecordDescriptor::from_record(&Record::new(None, vec![
+            ("%rec", "Item"),
+            ("%typedef", "Id_t Count_t"),
+            ("%typedef", "Count_t range 0 100"),
+            ("%type", "Id Id_t"),
+        ])).unwrap();
+        assert_eq!(d.field_type("Id"), Ok(Some(FieldType::Range(0, 100))));
+        assert_eq!(d.field_type("Title"), Ok(None));
+    }
+
+    #[test]
+    fn cyclic_typedefs() {
+        let d = RecordDescriptor::from_record(&Record::new(None, vec![
+            ("%rec", "Item"),
+            ("%typedef", "A_t B_t"),
+            ("%typedef", "B_t A_t"),
+            ("%type", "Id A_t"),
+        ])).unwrap();
+        assert!(d.field_type("Id").is_err());
+    }
+
+    #[test]
+    fn check_record() {
+        let d = RecordDescriptor::from_record(&Record::new(None, vec![
+            ("%rec", "Book"),
+            ("%type", "Location enum loaned home unknown"),
+        ])).unwrap();
+        let rec = Record::new(Some("Book"), vec![("Title", "Mio Cid"), ("Location", "lost")]);
+        assert_eq!(d.type_errors(&rec), vec![RecError::TypeMismatch {
+            name: "Location".to_owned(),
+            value: "lost".to_owned(),
+            typ: "enum loaned home unknown".to_owned(),
+        }]);
+    }
+}
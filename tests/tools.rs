@@ -0,0 +1,108 @@
+//! Tests which run the command-line tools on files in a temporary
+//! directory
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Write a recfile to a fresh temporary path for one test
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rrecutils-{}-{}.rec", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn run(tool: &str, args: &[&str]) -> Output {
+    Command::new(tool).args(args).output().unwrap()
+}
+
+#[test]
+fn rr_set_rejects_invalid_field_names() {
+    let src = "%rec: Book\n\nTitle: a\n";
+    let path = temp_file("set-names", src);
+    let file = path.to_str().unwrap();
+
+    let out = run(env!("CARGO_BIN_EXE_rr-set"), &[file, "-t", "Book", "-n", "0", "-f", "a:b", "-a", "z"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid field name: a:b"));
+    let out = run(env!("CARGO_BIN_EXE_rr-set"), &[file, "-f", "Title", "-r", "bad name"]);
+    assert!(!out.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), src);
+
+    let out = run(env!("CARGO_BIN_EXE_rr-set"), &[file, "-f", "Title", "-r", "Name"]);
+    assert!(out.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "%rec: Book\n\nName: a\n");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rr_ins_rejects_invalid_names() {
+    let src = "%rec: Book\n\nTitle: a\n";
+    let path = temp_file("ins-names", src);
+    let file = path.to_str().unwrap();
+
+    let out = run(env!("CARGO_BIN_EXE_rr-ins"), &[file, "-f", "Title: x", "-v", "y", "--force"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid field name: Title: x"));
+    let out = run(env!("CARGO_BIN_EXE_rr-ins"), &[file, "-t", "Bad Type", "-f", "Title", "-v", "y"]);
+    assert!(!out.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), src);
+
+    let out = run(env!("CARGO_BIN_EXE_rr-ins"), &[file, "-t", "Book", "-f", "Title", "-v", "b"]);
+    assert!(out.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "%rec: Book\n\nTitle: a\n\nTitle: b\n");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rr_to_json_fails_on_bad_input() {
+    let path = temp_file("tojson-bad", "a: 1\n\n bad\n");
+    let out = run(env!("CARGO_BIN_EXE_rr-to-json"), &["-i", path.to_str().unwrap()]);
+    assert!(!out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "[{\"a\":\"1\"}");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid line: bad"));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rr_sel_fails_on_bad_input() {
+    let path = temp_file("sel-bad", "a: 1\n\n bad\n");
+    let out = run(env!("CARGO_BIN_EXE_rr-sel"), &["-i", path.to_str().unwrap()]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid line: bad"));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rr_fix_reports_problems_position_first() {
+    let path = temp_file("fix", "%rec: A\n%mandatory: Id\n\nId: 1\n\n bad\n\nName: x\n");
+    let file = path.to_str().unwrap();
+    let out = run(env!("CARGO_BIN_EXE_rr-fix"), &[file]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], format!("{}:6:2: error: Invalid line: bad", file));
+    assert!(lines[1].starts_with(&format!("{}:8: error: ", file)));
+
+    let missing = format!("{}.missing", file);
+    let out = run(env!("CARGO_BIN_EXE_rr-fix"), &[&missing]);
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with(&format!("{}: error: ", missing)));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rr_sel_writes_each_descriptor_once() {
+    let path = temp_file("sel-descriptors", "%rec: A\n\nN: 3\n\n%rec: B\n\nN: 2\n\n%rec: A\n\nN: 1\n");
+    let file = path.to_str().unwrap();
+    for args in [vec!["--include-descriptors"], vec!["--include-descriptors", "-S", "N"]].iter() {
+        let mut args = args.clone();
+        args.extend(&["-i", file]);
+        let out = run(env!("CARGO_BIN_EXE_rr-sel"), &args);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert_eq!(stdout.matches("%rec: A").count(), 1);
+        assert_eq!(stdout.matches("%rec: B").count(), 1);
+    }
+    fs::remove_file(&path).unwrap();
+}